@@ -0,0 +1,480 @@
+//! File system access for coroutines
+//!
+//! Regular files are always "ready" for epoll, so their calls block the thread of the worker
+//! instead of the coroutine. The reads, writes and syncs of a [`File`] go through the io_uring
+//! of the worker while the calling coroutine is suspended. The other calls, and every call when
+//! the kernel does not support io_uring (before Linux 5.6, or forbidden by a seccomp filter),
+//! run on the blocking thread pool of the runtime (see [`spawn_blocking`](crate::spawn_blocking)).
+//! Outside of a runtime, the calls run on the calling thread.
+//!
+//! Each call costs a round trip to the kernel or the pool: buffer reads and writes, or use the
+//! whole-file helpers ([`read`], [`write`], [`Read::read_to_end`], [`Write::write_all`]).
+//!
+//! When the coroutine is cancelled, the pending call fails with an error wrapping
+//! [`Cancelled`](crate::Cancelled), the operation itself may still complete in the background.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+pub use std::fs::{DirEntry, Metadata, OpenOptions, Permissions};
+
+use crate::runtime::{self, UringOp};
+
+/// Number of entries read from a directory per call
+const READ_DIR_BATCH: usize = 32;
+
+/// Number of bytes read per io_uring operation by [`Read::read_to_end`]
+const READ_CHUNK: usize = 256 * 1024;
+
+/// Number of read buffers kept for reuse
+const BUFFER_CACHE: usize = 16;
+
+/// Largest read buffer kept for reuse
+const MAX_CACHED_BUFFER: usize = 1 << 20;
+
+/// The buffers the reads go through, shared by every runtime since the reads of the blocking
+/// pool run on its threads
+static BUFFERS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// Returns a buffer of at least `len` bytes
+fn take_buffer(len: usize) -> Vec<u8> {
+    let mut buffer = BUFFERS.lock().unwrap().pop().unwrap_or_default();
+    if buffer.len() < len {
+        buffer.resize(len, 0);
+    }
+    buffer
+}
+
+/// Gives a buffer back for the next reads
+fn put_buffer(buffer: Vec<u8>) {
+    if buffer.len() <= MAX_CACHED_BUFFER {
+        let mut buffers = BUFFERS.lock().unwrap();
+        if buffers.len() < BUFFER_CACHE {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Runs `f` on the blocking pool, or right away outside of a runtime
+fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    if runtime::current_worker().is_none() {
+        return f();
+    }
+    runtime::try_spawn_blocking(f)?
+}
+
+/// An open file, its calls suspend the current coroutine instead of the thread
+///
+/// The file implements [`Read`], [`Write`] and [`Seek`] (also through a shared reference).
+pub struct File {
+    std: Arc<std::fs::File>,
+}
+
+impl File {
+    /// Opens a file in read-only mode, see [`std::fs::File::open`]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path, OpenOptions::new().read(true))
+    }
+
+    /// Opens a file in write-only mode, creating or truncating it, see [`std::fs::File::create`]
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+    }
+
+    /// Opens a file with the given options
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let options = options.clone();
+        blocking(move || options.open(path)).map(Self::from_std)
+    }
+
+    /// Wraps a standard file
+    pub fn from_std(file: std::fs::File) -> Self {
+        Self {
+            std: Arc::new(file),
+        }
+    }
+
+    /// Returns the standard file, fails with the file back while a call of a cancelled
+    /// coroutine still uses it
+    pub fn try_into_std(self) -> Result<std::fs::File, Self> {
+        Arc::try_unwrap(self.std).map_err(|std| Self { std })
+    }
+
+    /// Queries the metadata of the file
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.with(|file| file.metadata())
+    }
+
+    /// Truncates or extends the file to `size` bytes
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        self.with(move |file| file.set_len(size))
+    }
+
+    /// Flushes the data and metadata of the file to the storage device
+    pub fn sync_all(&self) -> io::Result<()> {
+        match runtime::current_uring() {
+            Some(uring) => uring
+                .submit(&self.std, UringOp::Sync { data_only: false }, Vec::new())
+                .map(drop),
+            None => self.with(|file| file.sync_all()),
+        }
+    }
+
+    /// Flushes the data of the file to the storage device
+    pub fn sync_data(&self) -> io::Result<()> {
+        match runtime::current_uring() {
+            Some(uring) => uring
+                .submit(&self.std, UringOp::Sync { data_only: true }, Vec::new())
+                .map(drop),
+            None => self.with(|file| file.sync_data()),
+        }
+    }
+
+    /// Runs `f` with the standard file on the blocking pool
+    fn with<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&std::fs::File) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let file = self.std.clone();
+        blocking(move || f(&file))
+    }
+}
+
+impl Read for &File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len();
+        let (data, n) = match runtime::current_uring() {
+            Some(uring) => {
+                let mut data = take_buffer(len);
+                data.truncate(len);
+                let (n, data) = uring.submit(&self.std, UringOp::Read, data)?;
+                (data, n)
+            }
+            None => self.with(move |mut file| {
+                let mut data = take_buffer(len);
+                let n = file.read(&mut data[..len])?;
+                Ok((data, n))
+            })?,
+        };
+        buf[..n].copy_from_slice(&data[..n]);
+        put_buffer(data);
+        Ok(n)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let Some(uring) = runtime::current_uring() else {
+            let data = self.with(|mut file| {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                Ok(data)
+            })?;
+            buf.extend_from_slice(&data);
+            return Ok(data.len());
+        };
+        let mut chunk = vec![0; READ_CHUNK];
+        let mut total = 0;
+        loop {
+            let (n, data) = uring.submit(&self.std, UringOp::Read, chunk)?;
+            if n == 0 {
+                return Ok(total);
+            }
+            buf.extend_from_slice(&data[..n]);
+            total += n;
+            chunk = data;
+        }
+    }
+}
+
+impl Write for &File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data = buf.to_vec();
+        match runtime::current_uring() {
+            Some(uring) => uring
+                .submit(&self.std, UringOp::Write, data)
+                .map(|(n, _)| n),
+            None => self.with(move |mut file| file.write(&data)),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut data = buf.to_vec();
+        let Some(uring) = runtime::current_uring() else {
+            return self.with(move |mut file| file.write_all(&data));
+        };
+        while !data.is_empty() {
+            let (n, rest) = uring.submit(&self.std, UringOp::Write, data)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data = rest;
+            data.drain(..n);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for &File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.with(move |mut file| file.seek(pos))
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        (&*self).read_to_end(buf)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (&*self).write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        (&*self).seek(pos)
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File").field("std", &self.std).finish()
+    }
+}
+
+/// Reads the whole content of a file
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    if runtime::current_uring().is_some() {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        return Ok(data);
+    }
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::read(path))
+}
+
+/// Reads the whole content of a file as a string
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    if runtime::current_uring().is_some() {
+        return String::from_utf8(read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+    }
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::read_to_string(path))
+}
+
+/// Writes `contents` as the whole content of a file, creating or truncating it
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    if runtime::current_uring().is_some() {
+        return File::create(path)?.write_all(contents.as_ref());
+    }
+    let path = path.as_ref().to_owned();
+    let contents = contents.as_ref().to_vec();
+    blocking(move || std::fs::write(path, contents))
+}
+
+/// Queries the metadata of a path, following symbolic links
+pub fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::metadata(path))
+}
+
+/// Renames a file or directory, replacing the destination if it exists
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    blocking(move || std::fs::rename(from, to))
+}
+
+/// Removes a file
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::remove_file(path))
+}
+
+/// Creates a directory and all its missing parents
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::create_dir_all(path))
+}
+
+/// Returns an iterator over the entries of a directory
+pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
+    let path = path.as_ref().to_owned();
+    blocking(move || std::fs::read_dir(path)).map(|std| ReadDir {
+        std: Some(std),
+        entries: VecDeque::new(),
+    })
+}
+
+/// An iterator over the entries of a directory, see [`read_dir`]
+///
+/// The entries are read in batches, each batch suspends the current coroutine.
+pub struct ReadDir {
+    /// `None` once exhausted, or while a batch is read
+    std: Option<std::fs::ReadDir>,
+    entries: VecDeque<io::Result<DirEntry>>,
+}
+
+impl Iterator for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.entries.is_empty() {
+            let mut std = self.std.take()?;
+            let batch = blocking(move || {
+                let entries: VecDeque<_> = std.by_ref().take(READ_DIR_BATCH).collect();
+                let std = (entries.len() == READ_DIR_BATCH).then_some(std);
+                Ok((std, entries))
+            });
+            match batch {
+                Ok((std, entries)) => {
+                    self.std = std;
+                    self.entries = entries;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.entries.pop_front()
+    }
+}
+
+impl fmt::Debug for ReadDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadDir").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("coro-fs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_io() {
+        let dir = temp_dir("io");
+        let their_dir = dir.clone();
+        crate::run(move || {
+            let dir = their_dir;
+            let path = dir.join("a");
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"hello world").unwrap();
+            file.sync_all().unwrap();
+            assert_eq!(file.metadata().unwrap().len(), 11);
+
+            let mut file = File::open(&path).unwrap();
+            file.seek(SeekFrom::Start(6)).unwrap();
+            let mut buf = [0; 3];
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"wor");
+            let mut rest = Vec::new();
+            file.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, b"ld");
+
+            rename(&path, dir.join("b")).unwrap();
+            assert_eq!(read(dir.join("b")).unwrap(), b"hello world");
+            assert!(metadata(&path).is_err());
+            write(dir.join("c"), "c").unwrap();
+            let mut names: Vec<_> = read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            names.sort();
+            assert_eq!(names, ["b", "c"]);
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_write_metadata() {
+        let dir = temp_dir("rw");
+        let their_dir = dir.clone();
+        // Spans several chunks of `read_to_end`
+        let contents: Vec<u8> = (0..READ_CHUNK * 2 + 7).map(|i| i as u8).collect();
+        let their_contents = contents.clone();
+        crate::run(move || {
+            let path = their_dir.join("a");
+            let other = crate::spawn(|| 1);
+            write(&path, &their_contents).unwrap();
+            assert_eq!(read(&path).unwrap(), their_contents);
+            assert_eq!(metadata(&path).unwrap().len(), their_contents.len() as u64);
+            // The other coroutines ran meanwhile
+            assert!(other.is_finished());
+
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            assert_eq!(file.write(b"end").unwrap(), 3);
+            file.sync_data().unwrap();
+            assert_eq!(
+                file.metadata().unwrap().len(),
+                their_contents.len() as u64 + 3
+            );
+
+            let mut file = File::open(&path).unwrap();
+            let mut buf = vec![0; 10];
+            assert_eq!(file.read(&mut buf).unwrap(), 10);
+            assert_eq!(buf, their_contents[..10]);
+            file.seek(SeekFrom::End(-3)).unwrap();
+            assert_eq!(
+                read_to_string(&path).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+            let mut end = String::new();
+            file.read_to_string(&mut end).unwrap();
+            assert_eq!(end, "end");
+            assert!(File::open(their_dir.join("missing")).is_err());
+        });
+
+        // Outside of a runtime, the calls run on the calling thread
+        let path = dir.join("b");
+        write(&path, &contents).unwrap();
+        assert_eq!(read(&path).unwrap(), contents);
+        assert_eq!(metadata(&path).unwrap().len(), contents.len() as u64);
+        let mut file = File::open(&path).unwrap();
+        let mut buf = vec![0; 10];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[..10]);
+        let mut rest = Vec::new();
+        assert_eq!(file.read_to_end(&mut rest).unwrap(), contents.len() - 10);
+        file.sync_all().unwrap();
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use std::{io, os::fd::AsRawFd};
+
+use crate::{
+    runtime::{Interest, Registration},
+    select::{Source, Token},
+};
+
+/// A non-blocking I/O object registered in the reactor of the current worker
+pub(crate) struct PollEvented<E: AsRawFd> {
+    // Declared first: the registration is dropped while the file descriptor is still open
+    registration: Registration,
+    io: E,
+}
+
+impl<E: AsRawFd> PollEvented<E> {
+    /// Registers `io` in the reactor, `io` **MUST** already be in non-blocking mode
+    pub(crate) fn new(io: E) -> io::Result<Self> {
+        Ok(Self {
+            registration: Registration::new(io.as_raw_fd())?,
+            io,
+        })
+    }
+
+    #[inline(always)]
+    pub(crate) fn get_ref(&self) -> &E {
+        &self.io
+    }
+
+    /// Runs the non-blocking operation `f` on the I/O object, suspending the current coroutine
+    /// while it fails with `WouldBlock`
+    #[inline]
+    pub(crate) fn do_io<R>(
+        &self,
+        interest: Interest,
+        mut f: impl FnMut(&E) -> io::Result<R>,
+    ) -> io::Result<R> {
+        self.registration.do_io(interest, || f(&self.io))
+    }
+
+    /// Suspends the current coroutine until the I/O object is ready for `interest`
+    #[inline]
+    pub(crate) fn readiness(&self, interest: Interest) -> io::Result<()> {
+        self.registration.readiness(interest)
+    }
+
+    #[inline]
+    pub(crate) fn ready(&self, interest: Interest) -> Ready<'_> {
+        Ready {
+            registration: &self.registration,
+            interest,
+        }
+    }
+}
+
+/// The readiness of an I/O object for reading or writing, as a [`Source`] for
+/// [`Select`](crate::select::Select)
+///
+/// Readiness is a hint: the next operation may still fail with `WouldBlock`.
+pub struct Ready<'a> {
+    registration: &'a Registration,
+    interest: Interest,
+}
+
+impl Ready<'_> {
+    /// Suspends the current coroutine until the I/O object is ready, fails with `TimedOut` once
+    /// the deadline of the enclosing [`timeout`](crate::timeout) scope is reached
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn wait(self) -> io::Result<()> {
+        self.registration.readiness(self.interest)
+    }
+}
+
+impl Source for Ready<'_> {
+    type Output = ();
+
+    fn try_complete(&mut self) -> Option<()> {
+        self.registration.is_ready(self.interest).then_some(())
+    }
+
+    fn register(&mut self, token: &Token) {
+        self.registration.register(self.interest, token.waiter());
+    }
+
+    fn unregister(&mut self, token: &Token) {
+        self.registration.unregister(self.interest, token.waiter());
+    }
+}
+
+impl std::fmt::Debug for Ready<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ready")
+            .field("interest", &self.interest)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,20 @@
+pub mod fs;
+mod io;
+pub mod net;
+pub mod process;
+mod runtime;
+mod scope;
+pub mod select;
+pub mod signal;
+pub mod sync;
+pub mod time;
+
+pub use runtime::{
+    block_on, current, dump, is_cancelled, maybe_yield, metrics, run, spawn, spawn_blocking,
+    spawn_movable, yield_now, BlockedCoroutine, Builder, CancelToken, Cancelled, CoroutineBuilder,
+    CoroutineId, CoroutineInfo, CoroutineState, Deadlock, Histogram, JoinHandle, Metrics,
+    MetricsHandle, ParkReason, Priority, Runtime, WorkerMetrics,
+};
+pub use scope::{scope, Scope, ScopedJoinHandle};
+pub use time::{sleep, sleep_until, timeout, timeout_at};
+pub use ucontext::{coroutine_local, LocalKey};
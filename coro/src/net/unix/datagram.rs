@@ -0,0 +1,149 @@
+use std::{
+    io,
+    net::Shutdown,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use super::as_uninit;
+use crate::{
+    io::{PollEvented, Ready},
+    runtime::Interest,
+};
+
+/// A Unix datagram socket
+pub struct UnixDatagram {
+    io: PollEvented<Socket>,
+}
+
+impl UnixDatagram {
+    fn from_socket(socket: Socket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            io: PollEvented::new(socket)?,
+        })
+    }
+
+    /// Creates a socket bound to `path`
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = Socket::new(Domain::UNIX, Type::DGRAM, None)?;
+        socket.bind(&SockAddr::unix(path)?)?;
+        Self::from_socket(socket)
+    }
+
+    /// Creates a socket not bound to any address
+    pub fn unbound() -> io::Result<Self> {
+        Self::from_socket(Socket::new(Domain::UNIX, Type::DGRAM, None)?)
+    }
+
+    /// Returns an unnamed pair of connected sockets
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = Socket::pair(Domain::UNIX, Type::DGRAM, None)?;
+        Ok((Self::from_socket(a)?, Self::from_socket(b)?))
+    }
+
+    /// Registers a standard socket in the current runtime
+    pub fn from_std(socket: std::os::unix::net::UnixDatagram) -> io::Result<Self> {
+        Self::from_socket(Socket::from(OwnedFd::from(socket)))
+    }
+
+    /// Connects the socket to `path`, `send` and `recv` then only talk to this peer
+    pub fn connect<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.io.get_ref().connect(&SockAddr::unix(path)?)
+    }
+
+    /// Returns the readiness of the socket for reading, see [`Ready`]
+    pub fn readable(&self) -> Ready<'_> {
+        self.io.ready(Interest::READABLE)
+    }
+
+    /// Returns the readiness of the socket for writing, see [`Ready`]
+    pub fn writable(&self) -> Ready<'_> {
+        self.io.ready(Interest::WRITABLE)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SockAddr> {
+        self.io.get_ref().peer_addr()
+    }
+
+    /// Returns the value of the `SO_ERROR` option
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.io.get_ref().take_error()
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.io.get_ref().shutdown(how)
+    }
+
+    /// Sends `buf` to the connected peer
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        self.io.do_io(Interest::WRITABLE, |socket| socket.send(buf))
+    }
+
+    /// Sends `buf` to the socket named by `path`
+    pub fn send_to<P: AsRef<Path>>(&self, buf: &[u8], path: P) -> io::Result<usize> {
+        let addr = SockAddr::unix(path)?;
+        self.io
+            .do_io(Interest::WRITABLE, |socket| socket.send_to(buf, &addr))
+    }
+
+    /// Receives a datagram from the connected peer
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io
+            .do_io(Interest::READABLE, |socket| socket.recv(as_uninit(buf)))
+    }
+
+    /// Receives a datagram, returns its size and the address of its sender
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SockAddr)> {
+        self.io.do_io(Interest::READABLE, |socket| {
+            socket.recv_from(as_uninit(buf))
+        })
+    }
+
+    /// Sends `buf` along with the file descriptors `fds` to the connected peer
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        self.io.do_io(Interest::WRITABLE, |socket| {
+            super::sendmsg_with_fds(socket, buf, fds, None)
+        })
+    }
+
+    /// Sends `buf` along with the file descriptors `fds` to the socket named by `path`
+    pub fn send_to_with_fds<P: AsRef<Path>>(
+        &self,
+        buf: &[u8],
+        fds: &[RawFd],
+        path: P,
+    ) -> io::Result<usize> {
+        let addr = SockAddr::unix(path)?;
+        self.io.do_io(Interest::WRITABLE, |socket| {
+            super::sendmsg_with_fds(socket, buf, fds, Some(&addr))
+        })
+    }
+
+    /// Receives a datagram into `buf`, appending the received file descriptors to `fds`
+    ///
+    /// At most [`MAX_FDS`](super::MAX_FDS) file descriptors are received per call.
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.io.do_io(Interest::READABLE, |socket| {
+            super::recvmsg_with_fds(socket, buf, fds)
+        })
+    }
+}
+
+impl AsRawFd for UnixDatagram {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.get_ref().as_raw_fd()
+    }
+}
+
+impl AsFd for UnixDatagram {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.io.get_ref().as_fd()
+    }
+}
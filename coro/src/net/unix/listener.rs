@@ -0,0 +1,94 @@
+use std::{
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use super::UnixStream;
+use crate::{
+    io::{PollEvented, Ready},
+    runtime::Interest,
+};
+
+/// A Unix stream socket server, listening for connections
+pub struct UnixListener {
+    io: PollEvented<Socket>,
+}
+
+impl UnixListener {
+    const BACKLOG: libc::c_int = 128;
+
+    fn from_socket(socket: Socket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            io: PollEvented::new(socket)?,
+        })
+    }
+
+    /// Creates a listener bound to `path`
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        socket.bind(&SockAddr::unix(path)?)?;
+        socket.listen(Self::BACKLOG)?;
+        Self::from_socket(socket)
+    }
+
+    /// Registers a standard listener in the current runtime
+    pub fn from_std(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
+        Self::from_socket(Socket::from(OwnedFd::from(listener)))
+    }
+
+    /// Accepts a new incoming connection, suspending the current coroutine until one arrives
+    pub fn accept(&self) -> io::Result<(UnixStream, SockAddr)> {
+        let (socket, addr) = self
+            .io
+            .do_io(Interest::READABLE, |socket| socket.accept())?;
+        Ok((UnixStream::from_socket(socket)?, addr))
+    }
+
+    /// Returns an iterator over incoming connections
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
+    /// Returns the readiness of the listener for accepting a connection, see [`Ready`]
+    pub fn acceptable(&self) -> Ready<'_> {
+        self.io.ready(Interest::READABLE)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    /// Returns the value of the `SO_ERROR` option
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.io.get_ref().take_error()
+    }
+}
+
+impl AsRawFd for UnixListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.get_ref().as_raw_fd()
+    }
+}
+
+impl AsFd for UnixListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.io.get_ref().as_fd()
+    }
+}
+
+/// An iterator over the connections of a [`UnixListener`], never returns `None`
+pub struct Incoming<'a> {
+    listener: &'a UnixListener,
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = io::Result<UnixStream>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept().map(|(stream, _)| stream))
+    }
+}
@@ -0,0 +1,225 @@
+//! Unix domain sockets suspending the current coroutine instead of blocking its thread
+//!
+//! A socket is registered in the reactor of the runtime which created it. It may be moved to a
+//! coroutine of another runtime, which is then woken up by that reactor: the creating runtime
+//! must keep running for as long as the socket waits for readiness.
+
+use std::{
+    io::{self, IoSlice},
+    mem::MaybeUninit,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+};
+
+use socket2::{MaybeUninitSlice, MsgHdr, MsgHdrMut, SockAddr, Socket};
+
+mod datagram;
+mod listener;
+mod stream;
+
+pub use datagram::UnixDatagram;
+pub use listener::{Incoming, UnixListener};
+pub use stream::UnixStream;
+
+/// The maximum number of file descriptors received by a single `recv_with_fds` (`SCM_MAX_FD`)
+pub const MAX_FDS: usize = 253;
+
+#[inline(always)]
+fn as_uninit(buf: &mut [u8]) -> &mut [MaybeUninit<u8>] {
+    // Safety: the socket only writes initialized bytes to the buffer
+    unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) }
+}
+
+/// Returns a zeroed buffer aligned for `cmsghdr` with room for `n_fds` file descriptors
+fn control_buffer(n_fds: usize) -> Vec<u64> {
+    let space =
+        unsafe { libc::CMSG_SPACE((n_fds * std::mem::size_of::<libc::c_int>()) as u32) } as usize;
+    vec![0u64; space.div_ceil(std::mem::size_of::<u64>())]
+}
+
+/// Sends `buf` with `fds` attached as `SCM_RIGHTS` ancillary data
+fn sendmsg_with_fds(
+    socket: &Socket,
+    buf: &[u8],
+    fds: &[RawFd],
+    addr: Option<&SockAddr>,
+) -> io::Result<usize> {
+    let mut control = control_buffer(fds.len());
+    let control_len = std::mem::size_of_val(control.as_slice());
+    let bufs = [IoSlice::new(buf)];
+    let mut msg = MsgHdr::new().with_buffers(&bufs);
+    if let Some(addr) = addr {
+        msg = msg.with_addr(addr);
+    }
+    if fds.is_empty() {
+        return socket.sendmsg(&msg, 0);
+    }
+    unsafe {
+        let mut hdr: libc::msghdr = std::mem::zeroed();
+        hdr.msg_control = control.as_mut_ptr() as _;
+        hdr.msg_controllen = control_len as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of_val(fds) as u32) as _;
+        std::ptr::copy_nonoverlapping(
+            fds.as_ptr() as *const u8,
+            libc::CMSG_DATA(cmsg),
+            std::mem::size_of_val(fds),
+        );
+    }
+    let control = unsafe { std::slice::from_raw_parts(control.as_ptr() as *const u8, control_len) };
+    socket.sendmsg(&msg.with_control(control), 0)
+}
+
+/// Receives into `buf`, appending the file descriptors received as `SCM_RIGHTS` to `fds`
+fn recvmsg_with_fds(socket: &Socket, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+    let mut control = control_buffer(MAX_FDS);
+    let control_capacity = std::mem::size_of_val(control.as_slice());
+    let control_bytes = unsafe {
+        std::slice::from_raw_parts_mut(
+            control.as_mut_ptr() as *mut MaybeUninit<u8>,
+            control_capacity,
+        )
+    };
+    let mut bufs = [MaybeUninitSlice::new(as_uninit(buf))];
+    let mut msg = MsgHdrMut::new()
+        .with_buffers(&mut bufs)
+        .with_control(control_bytes);
+    let n = socket.recvmsg(&mut msg, libc::MSG_CMSG_CLOEXEC)?;
+    let control_len = msg.control_len();
+    unsafe {
+        let mut hdr: libc::msghdr = std::mem::zeroed();
+        hdr.msg_control = control.as_mut_ptr() as _;
+        hdr.msg_controllen = control_len as _;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const libc::c_int;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..(len / std::mem::size_of::<libc::c_int>()) {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        io::{Read, Seek, Write},
+        os::fd::AsRawFd,
+        path::PathBuf,
+    };
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("coro-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_stream_pair() {
+        crate::run(|| {
+            let (a, mut b) = UnixStream::pair().unwrap();
+            let reader = crate::spawn(move || {
+                let mut data = Vec::new();
+                b.read_to_end(&mut data).unwrap();
+                data
+            });
+            let mut a = a;
+            // Larger than the socket buffer: the writer must suspend until the reader drains it
+            let payload = vec![42u8; 4 << 20];
+            a.write_all(&payload).unwrap();
+            drop(a);
+            assert_eq!(reader.join().unwrap(), payload);
+        });
+    }
+
+    #[test]
+    fn test_listener() {
+        let path = socket_path("listener");
+        let their_path = path.clone();
+        crate::run(move || {
+            let listener = UnixListener::bind(&their_path).unwrap();
+            let client_path = their_path.clone();
+            let client = crate::spawn(move || {
+                let mut stream = UnixStream::connect(&client_path).unwrap();
+                stream.write_all(b"ping").unwrap();
+                let mut reply = [0u8; 4];
+                stream.read_exact(&mut reply).unwrap();
+                reply
+            });
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"ping");
+            stream.write_all(b"pong").unwrap();
+            assert_eq!(&client.join().unwrap(), b"pong");
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_datagram() {
+        let path = socket_path("datagram");
+        let their_path = path.clone();
+        crate::run(move || {
+            let server = UnixDatagram::bind(&their_path).unwrap();
+            let client = UnixDatagram::unbound().unwrap();
+            let receiver = crate::spawn(move || {
+                let mut buf = [0u8; 16];
+                let n = server.recv(&mut buf).unwrap();
+                buf[..n].to_vec()
+            });
+            client.send_to(b"hello", &their_path).unwrap();
+            assert_eq!(receiver.join().unwrap(), b"hello");
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_fd_passing() {
+        crate::run(|| {
+            let mut tmp = tempfile();
+            tmp.write_all(b"shared").unwrap();
+
+            let (a, b) = UnixStream::pair().unwrap();
+            assert_eq!(a.send_with_fds(b"fd", &[tmp.as_raw_fd()]).unwrap(), 2);
+            let mut buf = [0u8; 2];
+            let mut fds = Vec::new();
+            assert_eq!(b.recv_with_fds(&mut buf, &mut fds).unwrap(), 2);
+            assert_eq!(&buf, b"fd");
+            assert_eq!(fds.len(), 1);
+
+            let mut received = File::from(fds.pop().unwrap());
+            received.rewind().unwrap();
+            let mut content = String::new();
+            received.read_to_string(&mut content).unwrap();
+            assert_eq!(content, "shared");
+
+            let (a, b) = UnixDatagram::pair().unwrap();
+            a.send_with_fds(b"x", &[received.as_raw_fd()]).unwrap();
+            let mut fds = Vec::new();
+            assert_eq!(b.recv_with_fds(&mut buf, &mut fds).unwrap(), 1);
+            assert_eq!(fds.len(), 1);
+        });
+    }
+
+    fn tempfile() -> File {
+        let path = std::env::temp_dir().join(format!("coro-{}-fd", std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use crate::{
+    io::{PollEvented, Ready},
+    runtime::Interest,
+};
+
+/// A Unix stream socket
+pub struct UnixStream {
+    io: PollEvented<Socket>,
+}
+
+impl UnixStream {
+    pub(crate) fn from_socket(socket: Socket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            io: PollEvented::new(socket)?,
+        })
+    }
+
+    /// Connects to the socket named by `path`
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let addr = SockAddr::unix(path)?;
+        let stream = Self::from_socket(Socket::new(Domain::UNIX, Type::STREAM, None)?)?;
+        match stream.io.get_ref().connect(&addr) {
+            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {
+                stream.io.readiness(Interest::WRITABLE)?;
+                if let Some(err) = stream.io.get_ref().take_error()? {
+                    return Err(err);
+                }
+            }
+            result => result?,
+        }
+        Ok(stream)
+    }
+
+    /// Returns an unnamed pair of connected sockets
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = Socket::pair(Domain::UNIX, Type::STREAM, None)?;
+        Ok((Self::from_socket(a)?, Self::from_socket(b)?))
+    }
+
+    /// Registers a standard stream in the current runtime
+    pub fn from_std(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
+        Self::from_socket(Socket::from(OwnedFd::from(stream)))
+    }
+
+    /// Returns the readiness of the socket for reading, see [`Ready`]
+    pub fn readable(&self) -> Ready<'_> {
+        self.io.ready(Interest::READABLE)
+    }
+
+    /// Returns the readiness of the socket for writing, see [`Ready`]
+    pub fn writable(&self) -> Ready<'_> {
+        self.io.ready(Interest::WRITABLE)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SockAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SockAddr> {
+        self.io.get_ref().peer_addr()
+    }
+
+    /// Returns the value of the `SO_ERROR` option
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.io.get_ref().take_error()
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.io.get_ref().shutdown(how)
+    }
+
+    /// Sends `buf` along with the file descriptors `fds`, returns the number of bytes sent
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        self.io.do_io(Interest::WRITABLE, |socket| {
+            super::sendmsg_with_fds(socket, buf, fds, None)
+        })
+    }
+
+    /// Receives into `buf`, appending the received file descriptors to `fds`, returns the number
+    /// of bytes received
+    ///
+    /// At most [`MAX_FDS`](super::MAX_FDS) file descriptors are received per call.
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.io.do_io(Interest::READABLE, |socket| {
+            super::recvmsg_with_fds(socket, buf, fds)
+        })
+    }
+}
+
+impl Read for &UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io
+            .do_io(Interest::READABLE, |mut socket| socket.read(buf))
+    }
+}
+
+impl Read for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for &UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io
+            .do_io(Interest::WRITABLE, |mut socket| socket.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for UnixStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.get_ref().as_raw_fd()
+    }
+}
+
+impl AsFd for UnixStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.io.get_ref().as_fd()
+    }
+}
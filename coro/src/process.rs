@@ -0,0 +1,491 @@
+//! Child processes with pipes and waits suspending the current coroutine
+//!
+//! [`Command`] mirrors [`std::process::Command`]. The pipes of the child are registered in the
+//! reactor, and [`Child::wait`] parks on a pidfd (on kernels without pidfd, the wait runs on the
+//! blocking pool).
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+pub use std::process::{ExitStatus, Output, Stdio};
+
+use crate::{
+    io::{PollEvented, Ready},
+    runtime::{self, Interest},
+    scope,
+};
+
+/// A process builder, see [`std::process::Command`]
+pub struct Command {
+    std: std::process::Command,
+    stdin: bool,
+    stdout: bool,
+    stderr: bool,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            std: std::process::Command::new(program),
+            stdin: false,
+            stdout: false,
+            stderr: false,
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.std.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.std.args(args);
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Self {
+        self.std.env(key, val);
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.std.envs(vars);
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.std.env_remove(key);
+        self
+    }
+
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.std.env_clear();
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.std.current_dir(dir);
+        self
+    }
+
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.std.stdin(cfg);
+        self.stdin = true;
+        self
+    }
+
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.std.stdout(cfg);
+        self.stdout = true;
+        self
+    }
+
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.std.stderr(cfg);
+        self.stderr = true;
+        self
+    }
+
+    /// Spawns the command as a child process, its piped standard streams are registered in the
+    /// reactor of the current worker
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine runtime
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        Child::new(self.std.spawn()?)
+    }
+
+    /// Runs the command to completion, suspending the current coroutine, and collects its output
+    ///
+    /// Unless configured otherwise, stdin is null and stdout and stderr are captured.
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn output(&mut self) -> io::Result<Output> {
+        if !self.stdin {
+            self.std.stdin(Stdio::null());
+        }
+        if !self.stdout {
+            self.std.stdout(Stdio::piped());
+        }
+        if !self.stderr {
+            self.std.stderr(Stdio::piped());
+        }
+        let child = self.spawn();
+        // Restores the defaults of `spawn`
+        if !self.stdin {
+            self.std.stdin(Stdio::inherit());
+        }
+        if !self.stdout {
+            self.std.stdout(Stdio::inherit());
+        }
+        if !self.stderr {
+            self.std.stderr(Stdio::inherit());
+        }
+        child?.wait_with_output()
+    }
+
+    /// Runs the command to completion, suspending the current coroutine
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    /// Returns the standard builder, to set the options not mirrored here
+    pub fn as_std_mut(&mut self) -> &mut std::process::Command {
+        &mut self.std
+    }
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.std.fmt(f)
+    }
+}
+
+/// A running or exited child process, see [`std::process::Child`]
+pub struct Child {
+    std: std::process::Child,
+    /// `None` when the kernel does not support pidfd
+    pidfd: Option<PollEvented<OwnedFd>>,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+impl Child {
+    fn new(mut std: std::process::Child) -> io::Result<Self> {
+        let pidfd = match pidfd_open(std.id()) {
+            Ok(fd) => Some(PollEvented::new(fd)?),
+            Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            stdin: std.stdin.take().map(Pipe::new).transpose()?.map(ChildStdin),
+            stdout: std
+                .stdout
+                .take()
+                .map(Pipe::new)
+                .transpose()?
+                .map(ChildStdout),
+            stderr: std
+                .stderr
+                .take()
+                .map(Pipe::new)
+                .transpose()?
+                .map(ChildStderr),
+            pidfd,
+            std,
+        })
+    }
+
+    /// Returns the OS process id
+    pub fn id(&self) -> u32 {
+        self.std.id()
+    }
+
+    /// Kills the process with `SIGKILL`
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.std.kill()
+    }
+
+    /// Returns the exit status if the process exited, never suspends
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.std.try_wait()
+    }
+
+    /// Suspends the current coroutine until the process exits, closing its stdin first
+    ///
+    /// Fails with `TimedOut` once the deadline of the enclosing [`timeout`](crate::timeout)
+    /// scope is reached.
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        let Self { std, pidfd, .. } = self;
+        match pidfd {
+            Some(pidfd) => pidfd.do_io(Interest::READABLE, |_| match std.try_wait()? {
+                Some(status) => Ok(status),
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }),
+            None => {
+                let pid = std.id();
+                runtime::try_spawn_blocking(move || wait_exited(pid))??;
+                Ok(std
+                    .try_wait()?
+                    .expect("The child process exited but has no exit status"))
+            }
+        }
+    }
+
+    /// Suspends the current coroutine until the process exits, collecting its stdout and stderr
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn wait_with_output(mut self) -> io::Result<Output> {
+        drop(self.stdin.take());
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        scope(|s| {
+            let out = self.stdout.take().map(|mut pipe| {
+                let stdout = &mut stdout;
+                s.spawn(move || pipe.read_to_end(stdout))
+            });
+            if let Some(mut pipe) = self.stderr.take() {
+                pipe.read_to_end(&mut stderr)?;
+            }
+            if let Some(out) = out {
+                out.join()
+                    .unwrap_or_else(|payload| std::panic::resume_unwind(payload))?;
+            }
+            io::Result::Ok(())
+        })?;
+        Ok(Output {
+            status: self.wait()?,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+impl fmt::Debug for Child {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Child")
+            .field("pid", &self.id())
+            .field("stdin", &self.stdin)
+            .field("stdout", &self.stdout)
+            .field("stderr", &self.stderr)
+            .finish_non_exhaustive()
+    }
+}
+
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    set_nonblocking(&fd)?;
+    Ok(fd)
+}
+
+/// Blocks until the process exited, leaving it waitable
+fn wait_exited(pid: u32) -> io::Result<()> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A non-blocking pipe end registered in the reactor
+struct Pipe {
+    io: PollEvented<File>,
+}
+
+impl Pipe {
+    fn new<T: Into<OwnedFd>>(pipe: T) -> io::Result<Self> {
+        let fd = pipe.into();
+        set_nonblocking(&fd)?;
+        Ok(Self {
+            io: PollEvented::new(File::from(fd))?,
+        })
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.do_io(Interest::READABLE, |mut file| file.read(buf))
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.io
+            .do_io(Interest::WRITABLE, |mut file| file.write(buf))
+    }
+}
+
+/// The standard input of a child process, closed when dropped
+pub struct ChildStdin(Pipe);
+
+/// The standard output of a child process
+pub struct ChildStdout(Pipe);
+
+/// The standard error of a child process
+pub struct ChildStderr(Pipe);
+
+impl ChildStdin {
+    /// Returns the readiness of the pipe for writing, see [`Ready`]
+    pub fn writable(&self) -> Ready<'_> {
+        self.0.io.ready(Interest::WRITABLE)
+    }
+}
+
+impl Write for &ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ChildStdout {
+    /// Returns the readiness of the pipe for reading, see [`Ready`]
+    pub fn readable(&self) -> Ready<'_> {
+        self.0.io.ready(Interest::READABLE)
+    }
+}
+
+impl Read for &ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl ChildStderr {
+    /// Returns the readiness of the pipe for reading, see [`Ready`]
+    pub fn readable(&self) -> Ready<'_> {
+        self.0.io.ready(Interest::READABLE)
+    }
+}
+
+impl Read for &ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+macro_rules! impl_pipe_fd {
+    ($($pipe:ident),*) => {$(
+        impl AsRawFd for $pipe {
+            fn as_raw_fd(&self) -> RawFd {
+                self.0.io.get_ref().as_raw_fd()
+            }
+        }
+
+        impl AsFd for $pipe {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.io.get_ref().as_fd()
+            }
+        }
+
+        impl fmt::Debug for $pipe {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($pipe))
+                    .field("fd", &self.as_raw_fd())
+                    .finish()
+            }
+        }
+    )*};
+}
+
+impl_pipe_fd!(ChildStdin, ChildStdout, ChildStderr);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+
+    #[test]
+    fn test_process_pipes() {
+        crate::run(|| {
+            let mut child = Command::new("cat")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            let writer = spawn(move || stdin.write_all(b"hello coroutines"));
+            let mut output = String::new();
+            child
+                .stdout
+                .take()
+                .unwrap()
+                .read_to_string(&mut output)
+                .unwrap();
+            writer.join().unwrap().unwrap();
+            assert_eq!(output, "hello coroutines");
+            assert!(child.wait().unwrap().success());
+        });
+    }
+
+    #[test]
+    fn test_process_output() {
+        crate::run(|| {
+            let output = Command::new("/bin/sh")
+                .args(["-c", "echo out; echo err >&2; exit 3"])
+                .output()
+                .unwrap();
+            assert_eq!(output.status.code(), Some(3));
+            assert_eq!(output.stdout, b"out\n");
+            assert_eq!(output.stderr, b"err\n");
+
+            let status = Command::new("/bin/sh")
+                .args(["-c", "sleep 0.05"])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        });
+    }
+}
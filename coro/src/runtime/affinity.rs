@@ -0,0 +1,169 @@
+use std::{fs, io, mem};
+
+use ucontext::{StackPolicy, UContext};
+
+/// Number of stacks each worker keeps for reuse
+const STACK_CACHE: usize = 64;
+
+/// Where a worker thread runs and allocates its coroutine stacks
+pub(crate) struct Placement {
+    /// `None` to keep the affinity of the thread calling `Runtime::run`
+    cpus: Option<libc::cpu_set_t>,
+    node: Option<u32>,
+}
+
+impl Placement {
+    /// Returns the placement on `cpus`, and on the NUMA node of those CPUs unless `node` is given
+    pub(crate) fn new(cpus: Option<&[usize]>, node: Option<u32>) -> io::Result<Self> {
+        let Some(cpus) = cpus else {
+            return Ok(Self { cpus: None, node });
+        };
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {} is out of range", cpu),
+                ));
+            }
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        if cpus.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The CPU set of a worker is empty",
+            ));
+        }
+        let node = node.or_else(|| common_node(cpus));
+        Ok(Self {
+            cpus: Some(set),
+            node,
+        })
+    }
+
+    /// Applies the placement to the current thread until the guard is dropped
+    pub(crate) fn apply(&self) -> io::Result<PlacementGuard> {
+        let cpus = match &self.cpus {
+            Some(cpus) => {
+                let mut previous: libc::cpu_set_t = unsafe { mem::zeroed() };
+                check(unsafe {
+                    libc::sched_getaffinity(0, mem::size_of_val(&previous), &mut previous)
+                })?;
+                check(unsafe { libc::sched_setaffinity(0, mem::size_of_val(cpus), cpus) })?;
+                Some(previous)
+            }
+            None => None,
+        };
+        // An unplaced worker keeps the stack policy of its thread
+        let stacks = (self.cpus.is_some() || self.node.is_some()).then(|| {
+            UContext::set_stack_policy(StackPolicy {
+                cache: STACK_CACHE,
+                node: self.node,
+            })
+        });
+        Ok(PlacementGuard { cpus, stacks })
+    }
+
+    /// Returns the NUMA node of the worker, when known
+    #[inline(always)]
+    pub(crate) fn node(&self) -> Option<u32> {
+        self.node
+    }
+}
+
+/// Restores the affinity and the stack policy of the thread on drop
+pub(crate) struct PlacementGuard {
+    cpus: Option<libc::cpu_set_t>,
+    stacks: Option<StackPolicy>,
+}
+
+impl Drop for PlacementGuard {
+    fn drop(&mut self) {
+        if let Some(stacks) = self.stacks {
+            UContext::set_stack_policy(stacks);
+        }
+        if let Some(cpus) = &self.cpus {
+            unsafe { libc::sched_setaffinity(0, mem::size_of_val(cpus), cpus) };
+        }
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the NUMA node of a CPU, read from sysfs
+fn cpu_node(cpu: usize) -> Option<u32> {
+    fs::read_dir(format!("/sys/devices/system/cpu/cpu{}", cpu))
+        .ok()?
+        .filter_map(Result::ok)
+        .find_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()
+        })
+}
+
+/// Returns the NUMA node of every CPU of `cpus`, `None` when they span several nodes
+fn common_node(cpus: &[usize]) -> Option<u32> {
+    let node = cpu_node(cpus[0])?;
+    cpus[1..]
+        .iter()
+        .all(|&cpu| cpu_node(cpu) == Some(node))
+        .then_some(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Builder;
+
+    #[test]
+    fn test_worker_cpus() {
+        let mut allowed: libc::cpu_set_t = unsafe { mem::zeroed() };
+        check(unsafe { libc::sched_getaffinity(0, mem::size_of_val(&allowed), &mut allowed) })
+            .unwrap();
+        let cpu = (0..libc::CPU_SETSIZE as usize)
+            .find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &allowed) })
+            .unwrap();
+        let runtime = Builder::new().worker_cpus([cpu]).build().unwrap();
+        let running_on = runtime.run(|| unsafe { libc::sched_getcpu() });
+        assert_eq!(running_on as usize, cpu);
+
+        let mut restored: libc::cpu_set_t = unsafe { mem::zeroed() };
+        check(unsafe { libc::sched_getaffinity(0, mem::size_of_val(&restored), &mut restored) })
+            .unwrap();
+        assert!(unsafe { libc::CPU_EQUAL(&allowed, &restored) });
+        assert!(Builder::new().worker_cpus([]).build().is_err());
+
+        // The helper worker is pinned too
+        let runtime = Builder::new()
+            .worker_cpu_sets([[cpu], [cpu]])
+            .build()
+            .unwrap();
+        let running_on = runtime.run(|| {
+            let helper = unsafe {
+                crate::spawn_movable(|| {
+                    // Blocks the worker until the helper stole the other coroutine
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    libc::sched_getcpu()
+                })
+            };
+            let other = unsafe { crate::spawn_movable(|| libc::sched_getcpu()) };
+            (helper.join().unwrap(), other.join().unwrap())
+        });
+        assert_eq!(running_on, (cpu as i32, cpu as i32));
+        assert_eq!(runtime.metrics().workers.len(), 2);
+        assert!(Builder::new()
+            .worker_cpu_sets(Vec::<Vec<usize>>::new())
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::VecDeque,
+    io, panic,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{info::ParkReason, signal, task::Packet, worker};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    /// Threads waiting for a job
+    idle: usize,
+    /// Idle threads notified of a job that did not wake up yet
+    notified: usize,
+    shutdown: bool,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    condvar: Condvar,
+    max_threads: usize,
+    keep_alive: Duration,
+}
+
+/// A bounded pool of OS threads running the blocking calls of a runtime.
+///
+/// Threads are spawned on demand up to `max_threads` and exit after `keep_alive` without jobs.
+pub(crate) struct BlockingPool {
+    inner: Arc<Inner>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(max_threads: usize, keep_alive: Duration) -> Self {
+        assert!(
+            max_threads > 0,
+            "The blocking pool needs at least one thread"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                    notified: 0,
+                    shutdown: false,
+                }),
+                condvar: Condvar::new(),
+                max_threads,
+                keep_alive,
+            }),
+        }
+    }
+
+    /// Queues `job`, waking an idle thread or spawning a new one if the pool is not full
+    fn submit(&self, job: Job) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.queue.push_back(job);
+        if state.idle > state.notified {
+            state.notified += 1;
+            self.inner.condvar.notify_one();
+        } else if state.threads < self.inner.max_threads {
+            let inner = self.inner.clone();
+            let spawned = thread::Builder::new()
+                .name("coro-blocking".into())
+                .spawn(move || inner.run());
+            match spawned {
+                Ok(_) => state.threads += 1,
+                // The job runs on a busy thread once it is done
+                Err(err) if state.threads > 0 => {
+                    log::warn!("Failed to spawn a blocking thread: {}", err)
+                }
+                Err(err) => panic!("Failed to spawn a blocking thread: {}", err),
+            }
+        }
+    }
+}
+
+impl Drop for BlockingPool {
+    /// Lets the threads exit once the queued jobs ran, without waiting for them
+    fn drop(&mut self) {
+        self.inner.state.lock().unwrap().shutdown = true;
+        self.inner.condvar.notify_all();
+    }
+}
+
+impl Inner {
+    fn run(&self) {
+        signal::block_async_signals();
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.shutdown {
+                break;
+            }
+            state.idle += 1;
+            let (next, result) = self.condvar.wait_timeout(state, self.keep_alive).unwrap();
+            state = next;
+            state.idle -= 1;
+            if state.notified > 0 {
+                state.notified -= 1;
+            } else if result.timed_out() && state.queue.is_empty() {
+                break;
+            }
+        }
+        state.threads -= 1;
+    }
+}
+
+/// Runs `f` on the blocking thread pool of the runtime, suspending the current coroutine until
+/// it returns.
+///
+/// For the calls without non-blocking form (DNS resolution, file systems, CPU heavy libraries),
+/// which would stall every coroutine of the thread otherwise. When the coroutine is cancelled, it
+/// unwinds right away and the output of `f` is dropped once it returns.
+///
+/// # Panics
+///  - When called outside of a coroutine
+///  - When `f` panics
+pub fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match submit(f).wait(ParkReason::Blocking) {
+        Ok(output) => output,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Like [`spawn_blocking`], fails instead of unwinding when the coroutine is cancelled or
+/// reaches the deadline of its `timeout` scope, for the fallible I/O calls
+pub(crate) fn try_spawn_blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = submit(f);
+    if let Err(interrupt) = packet.try_wait_finished(ParkReason::Blocking) {
+        return Err(interrupt.into_io_error());
+    }
+    match packet.take() {
+        Some(Ok(output)) => Ok(output),
+        Some(Err(payload)) => panic::resume_unwind(payload),
+        None => unreachable!("The result of the blocking call was already taken"),
+    }
+}
+
+fn submit<F, T>(f: F) -> Arc<Packet<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let worker = worker::current().expect("`spawn_blocking` called outside of a coroutine runtime");
+    let packet = Packet::new();
+    let their_packet = packet.clone();
+    worker.blocking().submit(Box::new(move || {
+        their_packet.complete(panic::catch_unwind(panic::AssertUnwindSafe(f)))
+    }));
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawn, Builder};
+    use std::time::Instant;
+
+    #[test]
+    fn test_spawn_blocking() {
+        let runtime = Builder::new()
+            .max_blocking_threads(2)
+            .blocking_keep_alive(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        runtime.run(|| {
+            let start = Instant::now();
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    spawn(move || {
+                        spawn_blocking(move || {
+                            thread::sleep(Duration::from_millis(20));
+                            i
+                        })
+                    })
+                })
+                .collect();
+            let sum: u32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+            assert_eq!(sum, 6);
+            // Two threads for four jobs
+            assert!(start.elapsed() >= Duration::from_millis(40));
+
+            let payload = spawn(|| spawn_blocking(|| panic!("blocking")))
+                .join()
+                .unwrap_err();
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"blocking"));
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use std::{error, fmt, io, panic, sync::Arc};
+
+use super::{task::Task, worker};
+
+/// The error of a blocking call interrupted by the cancellation of its coroutine.
+///
+/// A cancelled coroutine is told once through the error of a fallible blocking call (I/O,
+/// sleeps), the next suspension unwinds it with `Cancelled` as panic payload. The blocking
+/// calls that cannot fail (locks, channels, joins, yields) unwind it right away.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("coroutine cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        io::Error::other(cancelled)
+    }
+}
+
+/// Unwinds the current coroutine after its cancellation
+///
+/// The payload is resumed without running the panic hook, the coroutine finishes with a
+/// [`Cancelled`] payload unless it catches the unwind.
+pub(crate) fn unwind() -> ! {
+    panic::resume_unwind(Box::new(Cancelled))
+}
+
+/// The panic payload unwinding a coroutine past the deadline of its [`timeout`](crate::timeout)
+/// scope, caught by the scope
+pub(crate) struct Expired;
+
+/// Why a blocking call gave up before completing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interrupt {
+    /// The coroutine was cancelled
+    Cancelled,
+    /// The deadline of the enclosing `timeout` scope was reached
+    Expired,
+}
+
+impl Interrupt {
+    /// Unwinds the current coroutine, for the blocking calls that cannot fail
+    pub(crate) fn unwind(self) -> ! {
+        match self {
+            Self::Cancelled => unwind(),
+            Self::Expired => panic::resume_unwind(Box::new(Expired)),
+        }
+    }
+
+    /// Returns the error of a fallible I/O call, telling the coroutine it was cancelled
+    pub(crate) fn into_io_error(self) -> io::Error {
+        match self {
+            Self::Cancelled => worker::current_task().deliver_cancel().into(),
+            Self::Expired => io::ErrorKind::TimedOut.into(),
+        }
+    }
+}
+
+/// A handle to cancel a coroutine, see [`JoinHandle::cancel_token`](super::JoinHandle)
+#[derive(Clone)]
+pub struct CancelToken {
+    task: Arc<Task>,
+}
+
+impl CancelToken {
+    pub(crate) fn new(task: Arc<Task>) -> Self {
+        Self { task }
+    }
+
+    /// Cancels the coroutine, waking it up if it is suspended. Returns `false` when it already
+    /// finished.
+    pub fn cancel(&self) -> bool {
+        self.task.cancel()
+    }
+
+    /// Returns `true` once the coroutine was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.task.is_cancelled()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Returns `true` when the current coroutine was cancelled, never suspends
+///
+/// Long computations without suspension points may check it to stop early.
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn is_cancelled() -> bool {
+    worker::current_task().is_cancelled()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::unix::UnixStream, spawn, sync::Mutex, time::sleep, yield_now};
+    use std::{
+        cell::Cell,
+        io::{ErrorKind, Read},
+        rc::Rc,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_cancel_io() {
+        crate::run(|| {
+            let (a, _b) = UnixStream::pair().unwrap();
+            let error = Rc::new(Cell::new(None));
+            let their_error = error.clone();
+            let handle = spawn(move || {
+                let err = (&a).read(&mut [0; 4]).unwrap_err();
+                their_error.set(Some(err.kind()));
+                assert!(is_cancelled());
+                // The next suspension unwinds
+                yield_now();
+                unreachable!();
+            });
+            yield_now();
+            assert!(handle.cancel());
+            let payload = handle.join().unwrap_err();
+            assert!(payload.is::<Cancelled>());
+            assert_eq!(error.get(), Some(ErrorKind::Other));
+        });
+    }
+
+    #[test]
+    fn test_cancel_sleep_and_lock() {
+        crate::run(|| {
+            let handle = spawn(|| sleep(Duration::from_secs(10)));
+            yield_now();
+            let token = handle.cancel_token();
+            assert!(token.cancel());
+            assert_eq!(handle.join().unwrap(), Err(Cancelled));
+            assert!(!token.cancel());
+
+            let mutex = Rc::new(Mutex::new(0));
+            let guard = mutex.lock().unwrap();
+            let their_mutex = mutex.clone();
+            let handle = spawn(move || *their_mutex.lock().unwrap() += 1);
+            yield_now();
+            assert!(handle.cancel());
+            assert!(handle.join().unwrap_err().is::<Cancelled>());
+            drop(guard);
+            assert_eq!(*mutex.lock().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_cancel_before_start() {
+        crate::run(|| {
+            let ran = Rc::new(Cell::new(false));
+            let their_ran = ran.clone();
+            let handle = spawn(move || their_ran.set(true));
+            assert!(handle.cancel());
+            assert!(handle.join().unwrap_err().is::<Cancelled>());
+            assert!(!ran.get());
+        });
+    }
+}
@@ -0,0 +1,243 @@
+use std::{fmt, sync::Arc};
+
+use ucontext::UContext;
+
+use super::{
+    info::{CoroutineId, CoroutineInfo, CoroutineState, ParkReason},
+    task::Task,
+};
+
+/// Called by the worker with the detected deadlocks, see
+/// [`Builder::on_deadlock`](crate::Builder::on_deadlock)
+pub(crate) type DeadlockHook = Box<dyn Fn(&Deadlock)>;
+
+/// The primitive a coroutine waits on, with a function returning the coroutine holding it
+#[derive(Clone, Copy)]
+pub(crate) struct Blocker {
+    resource: *const (),
+    holder: unsafe fn(*const ()) -> Option<CoroutineId>,
+}
+
+// Safety: `resource` is only dereferenced by the worker of the waiting coroutine, while it waits
+unsafe impl Send for Blocker {}
+unsafe impl Sync for Blocker {}
+
+impl Blocker {
+    /// A primitive without a holder
+    pub(crate) fn of<T>(resource: &T) -> Self {
+        unsafe fn none(_: *const ()) -> Option<CoroutineId> {
+            None
+        }
+        Self {
+            resource: resource as *const T as *const (),
+            holder: none,
+        }
+    }
+
+    /// A primitive held by `holder(resource)`
+    ///
+    /// # Safety
+    ///  - `holder` must accept `resource` as long as a coroutine waits on it
+    pub(crate) unsafe fn held<T>(
+        resource: &T,
+        holder: unsafe fn(*const ()) -> Option<CoroutineId>,
+    ) -> Self {
+        Self {
+            resource: resource as *const T as *const (),
+            holder,
+        }
+    }
+
+    fn holder(&self) -> Option<CoroutineId> {
+        unsafe { (self.holder)(self.resource) }
+    }
+}
+
+/// Returns the coroutine running on the current thread, recorded as the holder of the locks it
+/// acquires
+#[inline(always)]
+pub(crate) fn current_holder() -> Option<CoroutineId> {
+    UContext::current_id().map(CoroutineId::from_context)
+}
+
+/// A parked coroutine of a [`Deadlock`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct BlockedCoroutine {
+    pub coroutine: CoroutineInfo,
+    /// Address of the primitive it waits on, when known
+    pub resource: Option<usize>,
+    /// The coroutine holding that primitive, for locks and joins
+    pub holder: Option<CoroutineId>,
+}
+
+/// Every coroutine of a worker waits on a synchronization primitive, and no I/O, timer, blocking
+/// call or future can wake any of them up
+///
+/// Its [`Display`](fmt::Display) prints the wait-for graph.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Deadlock {
+    /// Ordered by identifier
+    pub coroutines: Vec<BlockedCoroutine>,
+    /// A cycle of coroutines each waiting on a primitive held by the next one, empty when none
+    /// was found
+    pub cycle: Vec<CoroutineId>,
+}
+
+impl Deadlock {
+    /// Returns the deadlock of `tasks`, `None` when one of them may still be woken up
+    pub(crate) fn detect<'a>(tasks: impl Iterator<Item = &'a Arc<Task>>) -> Option<Self> {
+        let mut coroutines = Vec::new();
+        for task in tasks {
+            let coroutine = task.info();
+            match coroutine.state {
+                CoroutineState::Parked(
+                    ParkReason::Join
+                    | ParkReason::Select
+                    | ParkReason::Mutex
+                    | ParkReason::RwLock
+                    | ParkReason::Condvar
+                    | ParkReason::Semaphore
+                    | ParkReason::Barrier
+                    | ParkReason::Channel,
+                ) => {}
+                _ => return None,
+            }
+            let blocker = task.blocker();
+            coroutines.push(BlockedCoroutine {
+                coroutine,
+                resource: blocker.map(|blocker| blocker.resource as usize),
+                holder: blocker.and_then(|blocker| blocker.holder()),
+            });
+        }
+        if coroutines.is_empty() {
+            return None;
+        }
+        coroutines.sort_by_key(|blocked| blocked.coroutine.id);
+        let cycle = find_cycle(&coroutines);
+        Some(Self { coroutines, cycle })
+    }
+
+    fn get(&self, id: CoroutineId) -> Option<&BlockedCoroutine> {
+        self.coroutines
+            .binary_search_by_key(&id, |blocked| blocked.coroutine.id)
+            .ok()
+            .map(|i| &self.coroutines[i])
+    }
+}
+
+/// Follows the holder edges, each coroutine waits on at most one holder
+fn find_cycle(coroutines: &[BlockedCoroutine]) -> Vec<CoroutineId> {
+    let holder = |id: CoroutineId| {
+        coroutines
+            .binary_search_by_key(&id, |blocked| blocked.coroutine.id)
+            .ok()
+            .and_then(|i| coroutines[i].holder)
+    };
+    for start in coroutines {
+        let mut path = vec![start.coroutine.id];
+        let mut next = start.holder;
+        while let Some(id) = next {
+            if let Some(i) = path.iter().position(|&other| other == id) {
+                return path.split_off(i);
+            }
+            if path.len() > coroutines.len() {
+                break;
+            }
+            path.push(id);
+            next = holder(id);
+        }
+    }
+    Vec::new()
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Deadlock: every coroutine waits on a synchronization primitive"
+        )?;
+        for blocked in &self.coroutines {
+            write!(f, "  {}", blocked.coroutine)?;
+            if let Some(resource) = blocked.resource {
+                write!(f, " at {:#x}", resource)?;
+            }
+            if let Some(holder) = blocked.holder {
+                match self
+                    .get(holder)
+                    .and_then(|holder| holder.coroutine.name.as_ref())
+                {
+                    Some(name) => write!(f, ", held by {} '{}'", holder, name)?,
+                    None => write!(f, ", held by {}", holder)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        if !self.cycle.is_empty() {
+            write!(f, "  cycle:")?;
+            for id in self.cycle.iter().chain(&self.cycle[..1]) {
+                write!(f, " {}", id)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, panic, rc::Rc, sync::Arc};
+
+    use crate::{runtime::Builder, sync::Mutex, yield_now, CoroutineBuilder};
+
+    fn lock_cycle() {
+        let a = Arc::new(Mutex::new(()));
+        let b = Arc::new(Mutex::new(()));
+        let (their_a, their_b) = (a.clone(), b.clone());
+        let other = CoroutineBuilder::new().name("other").spawn(move || {
+            let _b = their_b.lock().unwrap();
+            yield_now();
+            let _a = their_a.lock().unwrap();
+        });
+        let _a = a.lock().unwrap();
+        yield_now();
+        let _b = b.lock().unwrap();
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock_hook() {
+        let report = Rc::new(RefCell::new(None));
+        let their_report = report.clone();
+        let runtime = Builder::new()
+            .on_deadlock(move |deadlock| {
+                *their_report.borrow_mut() = Some(deadlock.clone());
+                panic!("deadlock");
+            })
+            .build()
+            .unwrap();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| runtime.run(lock_cycle)));
+        assert!(result.is_err());
+
+        let report = report.borrow();
+        let deadlock = report.as_ref().unwrap();
+        assert_eq!(deadlock.coroutines.len(), 2);
+        let (main, other) = (&deadlock.coroutines[0], &deadlock.coroutines[1]);
+        assert_eq!(main.holder, Some(other.coroutine.id));
+        assert_eq!(other.holder, Some(main.coroutine.id));
+        assert_eq!(deadlock.cycle, [main.coroutine.id, other.coroutine.id]);
+        let text = deadlock.to_string();
+        assert!(text.contains("'main': parked on mutex"), "{}", text);
+        assert!(text.contains("held by #"), "{}", text);
+    }
+
+    #[test]
+    fn test_deadlock_panics() {
+        let runtime = Builder::new().detect_deadlocks().build().unwrap();
+        let payload =
+            panic::catch_unwind(panic::AssertUnwindSafe(|| runtime.run(lock_cycle))).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(message.contains("cycle:"), "{}", message);
+    }
+}
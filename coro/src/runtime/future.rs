@@ -0,0 +1,161 @@
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+};
+
+use super::{
+    cancel::{self, Interrupt},
+    info::ParkReason,
+    task::Task,
+    worker,
+};
+
+/// Wakes a coroutine suspended in [`block_on`] by making its task runnable again
+///
+/// The waker only holds the task, it may be sent to and woken from any thread.
+struct TaskWaker(Arc<Task>);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion from inside a coroutine, suspending the coroutine (instead of the
+/// thread) until the future is woken.
+///
+/// The future may be woken up from any thread, including the coroutines of other runtimes.
+/// Spurious wake-ups only cost an extra poll. The future is dropped and the coroutine unwound
+/// when it is cancelled, see [`Cancelled`](crate::Cancelled), or when the deadline of the
+/// enclosing [`timeout`](crate::timeout) scope is reached.
+///
+/// # Panics
+///  - When called outside of a coroutine
+///  - When `future` panics
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let task = worker::current_task();
+    let waker = Waker::from(Arc::new(TaskWaker(task.clone())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        if task.is_cancelled() {
+            cancel::unwind();
+        }
+        match task.deadline() {
+            Some(deadline) => {
+                if worker::park_until(deadline, ParkReason::Future) {
+                    Interrupt::Expired.unwind();
+                }
+            }
+            None => worker::park(ParkReason::Future),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawn, yield_now};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    /// A future completed from another thread
+    #[derive(Default)]
+    struct Shared {
+        done: bool,
+        waker: Option<Waker>,
+    }
+
+    struct Remote(Arc<Mutex<Shared>>);
+
+    impl Future for Remote {
+        type Output = u32;
+
+        fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            let mut shared = self.0.lock().unwrap();
+            if shared.done {
+                Poll::Ready(42)
+            } else {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_on_remote_wake() {
+        crate::run(|| {
+            let shared = Arc::new(Mutex::new(Shared::default()));
+            let their_shared = shared.clone();
+            let thread = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                let mut shared = their_shared.lock().unwrap();
+                shared.done = true;
+                shared.waker.take().unwrap().wake();
+            });
+            // Other coroutines keep running meanwhile
+            let other = spawn(|| {
+                yield_now();
+                1
+            });
+            assert_eq!(block_on(Remote(shared)), 42);
+            assert_eq!(other.join().unwrap(), 1);
+            thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_block_on_across_runtimes() {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let their_shared = shared.clone();
+        let (handle_tx, handle_rx) = std::sync::mpsc::channel();
+        let (done_tx, done_rx) = crate::sync::oneshot::channel();
+        let other = thread::spawn(move || {
+            crate::run(move || {
+                crate::sleep(Duration::from_millis(10)).unwrap();
+                let mut shared = their_shared.lock().unwrap();
+                shared.done = true;
+                shared.waker.take().unwrap().wake();
+                drop(shared);
+                // Joined by a coroutine of the other runtime
+                let handle = spawn(|| {
+                    crate::sleep(Duration::from_millis(10)).unwrap();
+                    7
+                });
+                handle_tx.send(handle).unwrap();
+                done_rx.recv().unwrap();
+            })
+        });
+        crate::run(move || {
+            assert_eq!(block_on(Remote(shared)), 42);
+            let handle = handle_rx.recv().unwrap();
+            assert_eq!(block_on(handle).unwrap(), 7);
+            done_tx.send(()).unwrap();
+        });
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn test_await_join_handle() {
+        crate::run(|| {
+            let handle = spawn(|| {
+                yield_now();
+                7
+            });
+            let output = block_on(async move { handle.await.unwrap() + 1 });
+            assert_eq!(output, 8);
+        });
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use super::{
+    affinity::Placement,
+    blocking::BlockingPool,
+    signal, watchdog,
+    worker::{Shared, Worker},
+};
+
+struct Phase {
+    /// Incremented when a run starts
+    generation: u64,
+    /// Number of helpers running their coroutines
+    active: usize,
+    /// Set when the runtime is dropped
+    exit: bool,
+}
+
+struct Control {
+    phase: Mutex<Phase>,
+    changed: Condvar,
+    /// Cleared to stop the helpers at the end of a run
+    running: AtomicBool,
+}
+
+impl Control {
+    /// Waits for the next run, returns `false` when the runtime is dropped
+    fn wait_start(&self, generation: &mut u64) -> bool {
+        let mut phase = self.phase.lock().unwrap();
+        loop {
+            if phase.exit {
+                return false;
+            }
+            if phase.generation != *generation {
+                *generation = phase.generation;
+                return true;
+            }
+            phase = self.changed.wait(phase).unwrap();
+        }
+    }
+}
+
+/// Decrements the active helpers on drop, even when the helper panics
+struct Active<'a>(&'a Control);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        self.0.phase.lock().unwrap().active -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+/// The workers of a runtime besides the one running on the thread calling `Runtime::run`
+///
+/// Each helper owns a thread for the lifetime of the runtime, and runs its coroutines while
+/// `Runtime::run` executes.
+pub(crate) struct Helpers {
+    control: Arc<Control>,
+    shared: Vec<Arc<Shared>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Helpers {
+    /// Spawns a helper per placement, the helper `i` being the worker `i + 1` of the runtime
+    pub(crate) fn spawn(
+        placements: Vec<Placement>,
+        stack_size: usize,
+        blocking: &Arc<BlockingPool>,
+        watchdog: bool,
+    ) -> io::Result<Self> {
+        let mut helpers = Self {
+            control: Arc::new(Control {
+                phase: Mutex::new(Phase {
+                    generation: 0,
+                    active: 0,
+                    exit: false,
+                }),
+                changed: Condvar::new(),
+                running: AtomicBool::new(false),
+            }),
+            shared: Vec::with_capacity(placements.len()),
+            threads: Vec::with_capacity(placements.len()),
+        };
+        for (i, placement) in placements.into_iter().enumerate() {
+            let index = i + 1;
+            let (tx, rx) = mpsc::channel();
+            let control = helpers.control.clone();
+            let blocking = blocking.clone();
+            let thread = thread::Builder::new()
+                .name(format!("coro-worker-{}", index))
+                .spawn(move || {
+                    signal::block_async_signals();
+                    // The watchdog interrupts the stalled workers to capture their backtrace
+                    signal::unblock(watchdog::capture_signal());
+                    let started = placement.apply().and_then(|placement| {
+                        let worker = Worker::new(index, stack_size, blocking, watchdog, None)?;
+                        Ok((placement, worker))
+                    });
+                    let (_placement, worker) = match started {
+                        Ok(started) => started,
+                        Err(err) => {
+                            let _ = tx.send(Err(err));
+                            return;
+                        }
+                    };
+                    let _ = tx.send(Ok(worker.shared().clone()));
+                    run(&worker, &control);
+                })?;
+            helpers.threads.push(thread);
+            let shared = rx
+                .recv()
+                .map_err(|_| io::Error::other("A worker thread exited while starting"))??;
+            helpers.shared.push(shared);
+        }
+        Ok(helpers)
+    }
+
+    /// Returns the shared part of the helper workers, by index
+    pub(crate) fn shared(&self) -> &[Arc<Shared>] {
+        &self.shared
+    }
+
+    /// Lets the helpers run their coroutines until the guard is dropped
+    pub(crate) fn start(&self) -> Running<'_> {
+        if !self.threads.is_empty() {
+            self.control.running.store(true, Ordering::Release);
+            let mut phase = self.control.phase.lock().unwrap();
+            phase.generation += 1;
+            phase.active = self.threads.len();
+            self.control.changed.notify_all();
+        }
+        Running(self)
+    }
+}
+
+impl Drop for Helpers {
+    fn drop(&mut self) {
+        self.control.phase.lock().unwrap().exit = true;
+        self.control.changed.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Stops the helpers on drop, once they released their coroutines
+pub(crate) struct Running<'a>(&'a Helpers);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.0.threads.is_empty() {
+            return;
+        }
+        let control = &self.0.control;
+        control.running.store(false, Ordering::Release);
+        for shared in &self.0.shared {
+            shared.wake();
+        }
+        let mut phase = control.phase.lock().unwrap();
+        while phase.active > 0 {
+            phase = control.changed.wait(phase).unwrap();
+        }
+    }
+}
+
+fn run(worker: &Worker, control: &Control) {
+    let mut generation = 0;
+    while control.wait_start(&mut generation) {
+        let _active = Active(control);
+        let _guard = worker.enter();
+        worker.run_helper(&control.running);
+    }
+}
+
+/// Returns the order in which worker `index` steals from the other workers: the workers on its
+/// NUMA node first, each group starting after `index` so that the thieves spread over the
+/// victims
+pub(crate) fn steal_order(nodes: &[Option<u32>], index: usize) -> Vec<usize> {
+    let n = nodes.len();
+    let mut order: Vec<_> = (0..n).filter(|&other| other != index).collect();
+    order.sort_by_key(|&other| (nodes[other] != nodes[index], (other + n - index) % n));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::{spawn_movable, Builder},
+        sleep, yield_now,
+    };
+    use std::{collections::HashSet, time::Duration};
+
+    #[test]
+    fn test_steal_order() {
+        let nodes = [Some(0), Some(1), Some(0), Some(1), None];
+        assert_eq!(steal_order(&nodes, 0), vec![2, 1, 3, 4]);
+        assert_eq!(steal_order(&nodes, 3), vec![1, 4, 0, 2]);
+        assert_eq!(steal_order(&[None, None, None], 1), vec![2, 0]);
+        assert!(steal_order(&[Some(0)], 0).is_empty());
+    }
+
+    #[test]
+    fn test_worker_threads() {
+        let runtime = Builder::new().worker_threads(2).build().unwrap();
+        for _ in 0..2 {
+            let threads = runtime.run(|| {
+                let handles: Vec<_> = (0..8)
+                    .map(|i| unsafe {
+                        spawn_movable(move || {
+                            let mut threads = vec![thread::current().id()];
+                            // Blocks its worker, the others are stolen meanwhile
+                            thread::sleep(Duration::from_millis(20));
+                            threads.push(thread::current().id());
+                            sleep(Duration::from_millis(i)).unwrap();
+                            threads.push(thread::current().id());
+                            yield_now();
+                            threads.push(thread::current().id());
+                            threads
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect::<HashSet<_>>()
+            });
+            assert_eq!(threads.len(), 2);
+        }
+        assert_eq!(runtime.metrics().workers.len(), 2);
+    }
+
+    #[test]
+    fn test_worker_threads_deadlock_detection() {
+        let err = Builder::new()
+            .worker_threads(2)
+            .detect_deadlocks()
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{fmt, num::NonZeroU64, sync::Arc};
+
+use ucontext::{ContextId, UContext};
+
+use super::{queue::Priority, worker};
+
+/// A unique coroutine identifier, never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CoroutineId(NonZeroU64);
+
+impl CoroutineId {
+    /// A coroutine has the identifier of its context
+    #[inline(always)]
+    pub(crate) fn from_context(id: ContextId) -> Self {
+        Self(NonZeroU64::new(id.as_u64()).unwrap())
+    }
+
+    /// Returns `None` for 0
+    #[inline(always)]
+    pub(crate) fn from_u64(id: u64) -> Option<Self> {
+        NonZeroU64::new(id).map(Self)
+    }
+
+    #[inline(always)]
+    pub fn as_u64(self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl fmt::Display for CoroutineId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// What a parked coroutine waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ParkReason {
+    Io,
+    Sleep,
+    Join,
+    Blocking,
+    Future,
+    /// A select on synchronization primitives only
+    Select,
+    /// A select with an event made ready from outside of the runtime, e.g. I/O readiness
+    SelectExternal,
+    Mutex,
+    RwLock,
+    Condvar,
+    Semaphore,
+    Barrier,
+    Channel,
+}
+
+impl fmt::Display for ParkReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Io => "I/O",
+            Self::Sleep => "sleep",
+            Self::Join => "join",
+            Self::Blocking => "blocking call",
+            Self::Future => "future",
+            Self::Select => "select",
+            Self::SelectExternal => "select on external events",
+            Self::Mutex => "mutex",
+            Self::RwLock => "rwlock",
+            Self::Condvar => "condvar",
+            Self::Semaphore => "semaphore",
+            Self::Barrier => "barrier",
+            Self::Channel => "channel",
+        })
+    }
+}
+
+/// The scheduling state of a coroutine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineState {
+    /// Waiting in a run queue
+    Runnable,
+    Running,
+    /// Suspended until woken up
+    Parked(ParkReason),
+    /// Returned or panicked
+    Done,
+}
+
+impl fmt::Display for CoroutineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Runnable => f.write_str("runnable"),
+            Self::Running => f.write_str("running"),
+            Self::Parked(reason) => write!(f, "parked on {}", reason),
+            Self::Done => f.write_str("done"),
+        }
+    }
+}
+
+/// A snapshot of a coroutine, see [`current`] and [`dump`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CoroutineInfo {
+    pub id: CoroutineId,
+    pub name: Option<Arc<str>>,
+    pub state: CoroutineState,
+    pub priority: Priority,
+    /// The usable size of its stack, in bytes
+    pub stack_size: usize,
+}
+
+impl fmt::Display for CoroutineInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "coroutine {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " '{}'", name)?;
+        }
+        write!(f, ": {}, {} KiB stack", self.state, self.stack_size / 1024)
+    }
+}
+
+/// Returns the running coroutine, `None` outside of a coroutine
+pub fn current() -> Option<CoroutineInfo> {
+    let id = CoroutineId::from_context(UContext::current_id()?);
+    worker::current()?.task(id).map(|task| task.info())
+}
+
+/// Returns every live coroutine of the current worker, ordered by identifier
+///
+/// With several [`worker_threads`](crate::Builder::worker_threads), the coroutines of the other
+/// workers are not listed.
+///
+/// # Panics
+///  - When called outside of a coroutine runtime
+pub fn dump() -> Vec<CoroutineInfo> {
+    worker::current()
+        .expect("`dump` called outside of a coroutine runtime")
+        .dump()
+}
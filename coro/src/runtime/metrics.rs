@@ -0,0 +1,316 @@
+use std::{
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::worker;
+
+/// Upper bounds of the run duration buckets, in microseconds
+const RUN_BOUNDS_US: [u64; 11] = [
+    1, 4, 16, 64, 256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576,
+];
+
+/// Reads one value of a worker snapshot
+type Sample = fn(&WorkerMetrics) -> u64;
+
+/// The counters of a worker, only written by the worker thread except `remote_wakeups`
+#[derive(Default)]
+pub(crate) struct Counters {
+    context_switches: AtomicU64,
+    spawned: AtomicU64,
+    finished: AtomicU64,
+    run_queue_depth: AtomicU64,
+    reactor_polls: AtomicU64,
+    reactor_events: AtomicU64,
+    remote_wakeups: AtomicU64,
+    /// One more bucket for the durations above the last bound
+    run_buckets: [AtomicU64; RUN_BOUNDS_US.len() + 1],
+    run_sum_ns: AtomicU64,
+}
+
+#[inline(always)]
+fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+impl Counters {
+    #[inline(always)]
+    pub(crate) fn context_switches(&self) -> u64 {
+        self.context_switches.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_spawn(&self) {
+        add(&self.spawned, 1);
+    }
+
+    /// Records a coroutine that ran for `duration` before swapping back to the worker
+    #[inline]
+    pub(crate) fn on_run(&self, duration: Duration, finished: bool) {
+        // Into the coroutine, and back
+        add(&self.context_switches, 2);
+        if finished {
+            add(&self.finished, 1);
+        }
+        let us = duration.as_micros();
+        let bucket = RUN_BOUNDS_US
+            .iter()
+            .position(|&bound| us <= bound as u128)
+            .unwrap_or(RUN_BOUNDS_US.len());
+        add(&self.run_buckets[bucket], 1);
+        add(&self.run_sum_ns, duration.as_nanos() as u64);
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_run_queue_depth(&self, depth: usize) {
+        self.run_queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_reactor_poll(&self, events: usize) {
+        add(&self.reactor_polls, 1);
+        add(&self.reactor_events, events as u64);
+    }
+
+    #[inline(always)]
+    pub(crate) fn on_remote_wakeup(&self) {
+        add(&self.remote_wakeups, 1);
+    }
+
+    fn snapshot(&self, worker: usize) -> WorkerMetrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let buckets = RUN_BOUNDS_US
+            .iter()
+            .map(|&bound| Duration::from_micros(bound))
+            .chain([Duration::MAX])
+            .zip(self.run_buckets.iter().map(load))
+            .collect::<Vec<_>>();
+        WorkerMetrics {
+            worker,
+            context_switches: load(&self.context_switches),
+            spawned: load(&self.spawned),
+            finished: load(&self.finished),
+            run_queue_depth: load(&self.run_queue_depth),
+            reactor_polls: load(&self.reactor_polls),
+            reactor_events: load(&self.reactor_events),
+            remote_wakeups: load(&self.remote_wakeups),
+            run_duration: Histogram {
+                count: buckets.iter().map(|(_, count)| count).sum(),
+                buckets,
+                sum: Duration::from_nanos(load(&self.run_sum_ns)),
+            },
+        }
+    }
+}
+
+/// A distribution of durations
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Histogram {
+    /// Upper bound and number of samples of each bucket (not cumulative), the last bound is
+    /// `Duration::MAX`
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+/// A snapshot of the counters of a worker
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WorkerMetrics {
+    pub worker: usize,
+    /// Swaps between the worker and its coroutines
+    pub context_switches: u64,
+    pub spawned: u64,
+    pub finished: u64,
+    /// Runnable coroutines at the start of the last scheduler tick
+    pub run_queue_depth: u64,
+    /// How long the coroutines ran each time before swapping out
+    pub run_duration: Histogram,
+    /// Waits of the reactor for I/O events
+    pub reactor_polls: u64,
+    /// I/O events (and wakeups from other threads) returned by the reactor
+    pub reactor_events: u64,
+    /// Coroutines woken up from other threads
+    pub remote_wakeups: u64,
+}
+
+/// A snapshot of the runtime metrics, see [`metrics`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Metrics {
+    pub workers: Vec<WorkerMetrics>,
+}
+
+impl Metrics {
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.write_prometheus(&mut out).unwrap();
+        out
+    }
+
+    fn write_prometheus(&self, out: &mut String) -> fmt::Result {
+        let counters: [(&str, &str, Sample); 7] = [
+            (
+                "coro_context_switches_total",
+                "Swaps between the worker and its coroutines.",
+                |w| w.context_switches,
+            ),
+            ("coro_spawned_total", "Coroutines spawned.", |w| w.spawned),
+            ("coro_finished_total", "Coroutines finished.", |w| {
+                w.finished
+            }),
+            (
+                "coro_reactor_polls_total",
+                "Waits of the reactor for I/O events.",
+                |w| w.reactor_polls,
+            ),
+            (
+                "coro_reactor_events_total",
+                "Events returned by the reactor.",
+                |w| w.reactor_events,
+            ),
+            (
+                "coro_remote_wakeups_total",
+                "Coroutines woken up from other threads.",
+                |w| w.remote_wakeups,
+            ),
+            (
+                "coro_run_queue_depth",
+                "Runnable coroutines at the start of the last scheduler tick.",
+                |w| w.run_queue_depth,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let kind = if name.ends_with("_total") {
+                "counter"
+            } else {
+                "gauge"
+            };
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} {}", name, kind)?;
+            for worker in &self.workers {
+                writeln!(
+                    out,
+                    "{}{{worker=\"{}\"}} {}",
+                    name,
+                    worker.worker,
+                    value(worker)
+                )?;
+            }
+        }
+
+        let name = "coro_run_duration_seconds";
+        writeln!(
+            out,
+            "# HELP {} How long the coroutines ran each time before swapping out.",
+            name
+        )?;
+        writeln!(out, "# TYPE {} histogram", name)?;
+        for worker in &self.workers {
+            let histogram = &worker.run_duration;
+            let mut cumulative = 0;
+            for &(bound, count) in &histogram.buckets {
+                cumulative += count;
+                let le = if bound == Duration::MAX {
+                    "+Inf".to_string()
+                } else {
+                    bound.as_secs_f64().to_string()
+                };
+                writeln!(
+                    out,
+                    "{}_bucket{{worker=\"{}\",le=\"{}\"}} {}",
+                    name, worker.worker, le, cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "{}_sum{{worker=\"{}\"}} {}",
+                name,
+                worker.worker,
+                histogram.sum.as_secs_f64()
+            )?;
+            writeln!(
+                out,
+                "{}_count{{worker=\"{}\"}} {}",
+                name, worker.worker, histogram.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A handle to snapshot the metrics of a runtime from any thread, see
+/// [`Runtime::metrics_handle`](crate::Runtime::metrics_handle)
+#[derive(Clone)]
+pub struct MetricsHandle {
+    workers: Vec<Arc<worker::Shared>>,
+}
+
+impl MetricsHandle {
+    pub(crate) fn new(workers: Vec<Arc<worker::Shared>>) -> Self {
+        Self { workers }
+    }
+
+    pub fn snapshot(&self) -> Metrics {
+        Metrics {
+            workers: self
+                .workers
+                .iter()
+                .enumerate()
+                .map(|(i, shared)| shared.metrics().snapshot(i))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for MetricsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsHandle")
+            .field("workers", &self.workers.len())
+            .finish()
+    }
+}
+
+/// Returns a snapshot of the metrics of the current runtime
+///
+/// # Panics
+///  - When called outside of a coroutine runtime
+pub fn metrics() -> Metrics {
+    worker::current()
+        .expect("`metrics` called outside of a coroutine runtime")
+        .metrics_handle()
+        .snapshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawn, yield_now};
+
+    #[test]
+    fn test_metrics() {
+        crate::run(|| {
+            let handle = spawn(yield_now);
+            yield_now();
+            handle.join().unwrap();
+            let metrics = metrics();
+            let worker = &metrics.workers[0];
+            assert_eq!(worker.spawned, 2);
+            assert_eq!(worker.finished, 1);
+            assert!(worker.context_switches >= 6);
+            assert_eq!(worker.run_duration.count * 2, worker.context_switches);
+
+            let text = metrics.to_prometheus();
+            assert!(text.contains(
+                "# TYPE coro_spawned_total counter\ncoro_spawned_total{worker=\"0\"} 2\n"
+            ));
+            assert!(text.contains("coro_run_duration_seconds_bucket{worker=\"0\",le=\"+Inf\"}"));
+        });
+    }
+}
@@ -0,0 +1,475 @@
+use std::{io, iter, panic, sync::Arc, time::Duration};
+
+use ucontext::UContext;
+
+mod affinity;
+mod blocking;
+mod cancel;
+mod deadlock;
+mod future;
+mod helpers;
+mod info;
+mod metrics;
+mod preempt;
+mod queue;
+mod reactor;
+mod signal;
+mod task;
+mod timer;
+mod uring;
+mod waiter;
+mod watchdog;
+mod worker;
+
+pub use blocking::spawn_blocking;
+pub(crate) use blocking::try_spawn_blocking;
+pub use cancel::{is_cancelled, CancelToken, Cancelled};
+pub(crate) use cancel::{Expired, Interrupt};
+pub(crate) use deadlock::{current_holder, Blocker};
+pub use deadlock::{BlockedCoroutine, Deadlock};
+pub use future::block_on;
+pub use info::{current, dump, CoroutineId, CoroutineInfo, CoroutineState, ParkReason};
+pub use metrics::{metrics, Histogram, Metrics, MetricsHandle, WorkerMetrics};
+pub use queue::Priority;
+pub(crate) use reactor::{Interest, Registration};
+pub(crate) use task::Packet;
+pub use task::{CoroutineBuilder, JoinHandle};
+pub(crate) use uring::{current as current_uring, UringOp};
+pub(crate) use waiter::{WaitQueue, Waiter};
+pub(crate) use worker::{current as current_worker, current_task, park_until, preemption_point};
+
+/// The CPUs the workers are pinned to
+enum WorkerCpus {
+    /// The affinity of the threads creating the workers
+    Inherit,
+    /// The same CPUs for every worker
+    Shared(Vec<usize>),
+    /// A set of CPUs per worker
+    PerWorker(Vec<Vec<usize>>),
+}
+
+/// Runtime configuration
+pub struct Builder {
+    stack_size: usize,
+    max_blocking_threads: usize,
+    blocking_keep_alive: Duration,
+    watchdog: Option<Duration>,
+    time_slice: Option<Duration>,
+    worker_threads: usize,
+    cpus: WorkerCpus,
+    numa_node: Option<u32>,
+    on_deadlock: Option<deadlock::DeadlockHook>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            stack_size: UContext::default_size(),
+            max_blocking_threads: 64,
+            blocking_keep_alive: Duration::from_secs(10),
+            watchdog: None,
+            time_slice: None,
+            worker_threads: 1,
+            cpus: WorkerCpus::Inherit,
+            numa_node: None,
+            on_deadlock: None,
+        }
+    }
+
+    /// Sets the stack size (in bytes) of the spawned coroutines
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Sets the maximum number of threads running [`spawn_blocking`] calls, 64 by default
+    ///
+    /// # Panics
+    ///  - When `max` is 0
+    pub fn max_blocking_threads(mut self, max: usize) -> Self {
+        assert!(max > 0, "The blocking pool needs at least one thread");
+        self.max_blocking_threads = max;
+        self
+    }
+
+    /// Sets how long an idle blocking thread waits for a new call before exiting, 10 seconds by
+    /// default
+    pub fn blocking_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.blocking_keep_alive = keep_alive;
+        self
+    }
+
+    /// Starts a watchdog thread logging (as warnings) the coroutines running for longer than
+    /// `threshold` without yielding, with their backtrace
+    ///
+    /// The backtrace is captured by the stalled thread itself, interrupted by the real-time
+    /// signal `SIGRTMIN + 4` which must not be used by the application. It follows the frame
+    /// pointers of the coroutine: build with `-C force-frame-pointers=yes` for complete
+    /// backtraces.
+    pub fn watchdog(mut self, threshold: Duration) -> Self {
+        self.watchdog = Some(threshold);
+        self
+    }
+
+    /// Enables soft preemption: a coroutine running for longer than `slice` without switching
+    /// yields at its next [`maybe_yield`] call, also made by the I/O and lock calls of the
+    /// runtime
+    ///
+    /// A helper thread checks the workers every `slice`, a coroutine runs for one to two slices
+    /// before being asked to yield. Coroutines are never interrupted asynchronously.
+    pub fn time_slice(mut self, slice: Duration) -> Self {
+        self.time_slice = Some(slice);
+        self
+    }
+
+    /// Sets the number of workers, 1 by default
+    ///
+    /// The first worker runs on the thread calling [`Runtime::run`], the other ones on threads
+    /// of their own living as long as the runtime. A coroutine runs on the worker which spawned
+    /// it, unless it was spawned by [`spawn_movable`]: an idle worker then steals it, from the
+    /// workers on its NUMA node first.
+    ///
+    /// # Panics
+    ///  - When `n` is 0
+    pub fn worker_threads(mut self, n: usize) -> Self {
+        assert!(n > 0, "A runtime needs at least one worker");
+        self.worker_threads = n;
+        self
+    }
+
+    /// Pins every worker to `cpus` while [`Runtime::run`] executes, the previous affinity of the
+    /// calling thread is restored when it returns
+    ///
+    /// The coroutine stacks are bound to the NUMA node of those CPUs when they all belong to the
+    /// same node, see [`numa_node`](Self::numa_node). [`build`](Self::build) fails when the set is
+    /// empty or out of range.
+    pub fn worker_cpus<I: IntoIterator<Item = usize>>(mut self, cpus: I) -> Self {
+        self.cpus = WorkerCpus::Shared(cpus.into_iter().collect());
+        self
+    }
+
+    /// Starts a worker per set of `sets`, pinned to the CPUs of its set, like
+    /// [`worker_cpus`](Self::worker_cpus). The number of sets overrides
+    /// [`worker_threads`](Self::worker_threads).
+    ///
+    /// Each worker allocates its coroutine stacks on the NUMA node of its CPUs and keeps its own
+    /// pool of released stacks, the idle workers steal from the workers of their node first.
+    /// [`build`](Self::build) fails when there is no set.
+    pub fn worker_cpu_sets<I, S>(mut self, sets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: IntoIterator<Item = usize>,
+    {
+        self.cpus = WorkerCpus::PerWorker(
+            sets.into_iter()
+                .map(|cpus| cpus.into_iter().collect())
+                .collect(),
+        );
+        self
+    }
+
+    /// Binds the memory of the coroutine stacks of every worker to NUMA `node`, overriding the
+    /// node of their CPUs. The workers keep their released stacks for reuse, so they stay on
+    /// that node.
+    ///
+    /// The workers are considered to be on `node` when ordering the victims of work stealing.
+    pub fn numa_node(mut self, node: u32) -> Self {
+        self.numa_node = Some(node);
+        self
+    }
+
+    /// Panics when every coroutine waits on a synchronization primitive (lock, channel, join...)
+    /// and no I/O, timer, blocking call or future can wake any of them up, instead of sleeping
+    /// forever. The panic message is the wait-for graph of the coroutines, see [`Deadlock`].
+    ///
+    /// Disabled by default: a primitive shared with a thread outside of the runtime may still
+    /// wake a coroutine up, such deadlocks are false positives. [`build`](Self::build) fails
+    /// when the runtime has several workers.
+    pub fn detect_deadlocks(self) -> Self {
+        self.on_deadlock(|deadlock| panic!("{}", deadlock))
+    }
+
+    /// Like [`detect_deadlocks`](Self::detect_deadlocks) but calls `hook` instead of panicking,
+    /// on the worker thread. The worker goes back to sleep when the hook returns, a panic of the
+    /// hook is propagated by [`Runtime::run`].
+    pub fn on_deadlock<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Deadlock) + 'static,
+    {
+        self.on_deadlock = Some(Box::new(hook));
+        self
+    }
+
+    pub fn build(self) -> io::Result<Runtime> {
+        let placements = match &self.cpus {
+            WorkerCpus::Inherit => (0..self.worker_threads)
+                .map(|_| affinity::Placement::new(None, self.numa_node))
+                .collect::<io::Result<Vec<_>>>()?,
+            WorkerCpus::Shared(cpus) => (0..self.worker_threads)
+                .map(|_| affinity::Placement::new(Some(cpus), self.numa_node))
+                .collect::<io::Result<Vec<_>>>()?,
+            WorkerCpus::PerWorker(sets) if sets.is_empty() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "A runtime needs at least one CPU set",
+                ));
+            }
+            WorkerCpus::PerWorker(sets) => sets
+                .iter()
+                .map(|cpus| affinity::Placement::new(Some(cpus), self.numa_node))
+                .collect::<io::Result<Vec<_>>>()?,
+        };
+        if placements.len() > 1 && self.on_deadlock.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Deadlocks are only detected with a single worker",
+            ));
+        }
+        let nodes: Vec<_> = placements.iter().map(affinity::Placement::node).collect();
+        let mut placements = placements.into_iter();
+        let placement = placements.next().unwrap();
+
+        let blocking = Arc::new(blocking::BlockingPool::new(
+            self.max_blocking_threads,
+            self.blocking_keep_alive,
+        ));
+        let helpers = helpers::Helpers::spawn(
+            placements.collect(),
+            self.stack_size,
+            &blocking,
+            self.watchdog.is_some(),
+        )?;
+        let worker = worker::Worker::new(
+            0,
+            self.stack_size,
+            blocking,
+            self.watchdog.is_some(),
+            self.on_deadlock,
+        )?;
+        let workers: Vec<_> = iter::once(worker.shared().clone())
+            .chain(helpers.shared().iter().cloned())
+            .collect();
+        for (i, shared) in workers.iter().enumerate() {
+            shared.set_peers(
+                helpers::steal_order(&nodes, i)
+                    .into_iter()
+                    .map(|victim| Arc::downgrade(&workers[victim]))
+                    .collect(),
+            );
+        }
+
+        let watchdog = self
+            .watchdog
+            .map(|threshold| watchdog::Watchdog::start(threshold, workers.clone()));
+        let ticker = self
+            .time_slice
+            .map(|slice| preempt::Ticker::start(slice, workers));
+        Ok(Runtime {
+            worker,
+            placement,
+            helpers,
+            _watchdog: watchdog,
+            _ticker: ticker,
+        })
+    }
+}
+
+/// A coroutine runtime running its first worker on the calling thread, and the other ones on
+/// threads of their own, see [`Builder::worker_threads`]
+pub struct Runtime {
+    worker: worker::Worker,
+    placement: affinity::Placement,
+    helpers: helpers::Helpers,
+    /// Stopped when the runtime is dropped
+    _watchdog: Option<watchdog::Watchdog>,
+    _ticker: Option<preempt::Ticker>,
+}
+
+impl Runtime {
+    /// Returns a runtime with the default configuration
+    pub fn new() -> io::Result<Self> {
+        Builder::new().build()
+    }
+
+    /// Runs `f` as the main coroutine and returns its output.
+    ///
+    /// The other workers run their coroutines meanwhile. Returns as soon as `f` returns, the
+    /// coroutines still running on any worker are released.
+    ///
+    /// # Panics
+    ///  - When called from inside a runtime
+    ///  - When the calling thread cannot be pinned to the CPUs of [`Builder::worker_cpus`]
+    ///  - When `f` panics
+    pub fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        assert!(
+            worker::current().is_none(),
+            "Cannot start a runtime from within a runtime"
+        );
+        let _placement = self
+            .placement
+            .apply()
+            .expect("Failed to place the worker thread");
+        let _guard = self.worker.enter();
+        let _helpers = self.helpers.start();
+        match self.worker.run(f) {
+            Ok(output) => output,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Returns a snapshot of the metrics of the runtime
+    pub fn metrics(&self) -> Metrics {
+        self.metrics_handle().snapshot()
+    }
+
+    /// Returns a handle to snapshot the metrics of the runtime from any thread, e.g. to serve
+    /// them while the runtime runs
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        self.worker.metrics_handle()
+    }
+}
+
+/// Runs `f` as the main coroutine of a new runtime and returns its output
+///
+/// # Panics
+///  - When the runtime cannot be created
+///  - When called from inside a runtime
+///  - When `f` panics
+pub fn run<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + 'static,
+    T: 'static,
+{
+    Runtime::new()
+        .expect("Failed to create the coroutine runtime")
+        .run(f)
+}
+
+/// Spawns a new coroutine on the current runtime, returning a [`JoinHandle`] for it
+///
+/// # Panics
+///  - When called outside of a runtime
+///  - When the coroutine stack cannot be allocated
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + 'static,
+    T: 'static,
+{
+    CoroutineBuilder::new().spawn(f)
+}
+
+/// Spawns a new coroutine on the current runtime which idle workers may steal, to resume it on
+/// their thread, see [`Builder::worker_threads`]
+///
+/// The coroutines spawned by [`spawn`] always run on the worker which spawned them. A movable
+/// coroutine also stays on its worker while it sleeps, waits for a timeout, a signal, or the
+/// coroutines of a [`scope`](crate::scope).
+///
+/// # Safety
+/// The coroutine may suspend on one thread and resume on another:
+///  - The values it keeps across a suspension must be `Send`, e.g. no `Rc`, `RefCell` borrow
+///    or `MutexGuard` held across a lock, channel, I/O, join or yield call
+///  - The values it shares with the coroutines it spawns must be `Sync`
+///  - It must not keep references to thread-local values across a suspension
+///
+/// # Panics
+///  - When called outside of a runtime
+///  - When the coroutine stack cannot be allocated
+pub unsafe fn spawn_movable<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    unsafe { CoroutineBuilder::new().spawn_movable(f) }
+}
+
+/// Lets the other runnable coroutines run before resuming the current one
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn yield_now() {
+    worker::yield_now()
+}
+
+/// Yields like [`yield_now`] when the current coroutine exhausted its time slice, see
+/// [`Builder::time_slice`], returns right away otherwise or outside of a coroutine
+pub fn maybe_yield() {
+    if worker::should_yield() {
+        worker::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_spawn_join() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let their_order = order.clone();
+        let sum = run(move || {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let order = their_order.clone();
+                    spawn(move || {
+                        order.borrow_mut().push(i);
+                        yield_now();
+                        order.borrow_mut().push(i + 4);
+                        i
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum::<i32>()
+        });
+        assert_eq!(sum, 6);
+        assert_eq!(*order.borrow(), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_join_panic() {
+        run(|| {
+            let handle = spawn(|| panic!("boom"));
+            let payload = handle.join().unwrap_err();
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        });
+    }
+
+    #[test]
+    fn test_introspection() {
+        run(|| {
+            let main = current().unwrap();
+            assert_eq!(main.name.as_deref(), Some("main"));
+            assert_eq!(main.state, CoroutineState::Running);
+
+            let (tx, rx) = crate::sync::mpsc::channel::<()>();
+            let handle = CoroutineBuilder::new()
+                .name("receiver")
+                .spawn(move || rx.recv().unwrap());
+            assert_eq!(handle.name(), Some("receiver"));
+            let info = |id| dump().into_iter().find(|info| info.id == id).unwrap();
+            assert_eq!(info(handle.id()).state, CoroutineState::Runnable);
+            yield_now();
+            let receiver = info(handle.id());
+            assert_eq!(receiver.state, CoroutineState::Parked(ParkReason::Channel));
+            assert!(receiver.stack_size > 0);
+            assert!(receiver
+                .to_string()
+                .contains("'receiver': parked on channel"));
+            tx.send(()).unwrap();
+            handle.join().unwrap();
+            assert_eq!(dump().len(), 1);
+        });
+        assert!(current().is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{signal, worker::Shared};
+
+/// A thread requesting the coroutines running for longer than a time slice to yield, see
+/// [`maybe_yield`](crate::maybe_yield)
+///
+/// The request is a flag checked by the coroutines, which are never interrupted.
+pub(crate) struct Ticker {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Ticker {
+    pub(crate) fn start(slice: Duration, workers: Vec<Arc<Shared>>) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let their_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("coro-preempt".to_string())
+            .spawn(move || {
+                signal::block_async_signals();
+                run(slice, &workers, &their_stop);
+            })
+            .expect("Failed to spawn the coroutine preemption ticker");
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(slice: Duration, workers: &[Arc<Shared>], stop: &(Mutex<bool>, Condvar)) {
+    // A worker that did not switch during a whole slice runs the same coroutine since then
+    let mut switches = vec![0; workers.len()];
+    let mut stopped = stop.0.lock().unwrap();
+    while !*stopped {
+        stopped = stop.1.wait_timeout(stopped, slice).unwrap().0;
+        for (worker, switches) in workers.iter().zip(&mut switches) {
+            let current = worker.metrics().context_switches();
+            if current == *switches {
+                worker.request_yield();
+            }
+            *switches = current;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{maybe_yield, runtime::Builder, spawn};
+
+    #[test]
+    fn test_maybe_yield() {
+        let runtime = Builder::new()
+            .time_slice(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        runtime.run(|| {
+            let done = Rc::new(Cell::new(false));
+            let their_done = done.clone();
+            let other = spawn(move || their_done.set(true));
+            let start = Instant::now();
+            // Never yields without preemption
+            while !done.get() {
+                assert!(start.elapsed() < Duration::from_secs(10));
+                maybe_yield();
+            }
+            other.join().unwrap();
+        });
+    }
+}
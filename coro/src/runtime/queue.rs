@@ -0,0 +1,181 @@
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+use super::task::Task;
+
+/// Scheduling priority of a coroutine, see [`CoroutineBuilder::priority`]
+///
+/// The worker always resumes the runnable coroutines of the highest priority first, except for
+/// the coroutines left waiting behind them for too long, which are resumed next. Priorities only
+/// order the coroutines of one worker: the coroutines of different workers and runtimes do not
+/// compete with each other.
+///
+/// [`CoroutineBuilder::priority`]: crate::CoroutineBuilder::priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Background work
+    Low,
+    #[default]
+    Normal,
+    /// Latency-sensitive work
+    High,
+}
+
+impl Priority {
+    const LEVELS: usize = 3;
+
+    #[inline(always)]
+    fn level(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        })
+    }
+}
+
+/// A runnable task waiting behind higher priorities is resumed once this many tasks were
+/// resumed since it was queued
+const AGING: u64 = 32;
+
+/// The run queue of a worker, one FIFO queue per priority
+#[derive(Default)]
+pub(crate) struct RunQueue {
+    /// The tasks with the number of tasks popped when they were queued
+    levels: [VecDeque<(u64, Arc<Task>)>; Priority::LEVELS],
+    popped: u64,
+}
+
+impl RunQueue {
+    pub(crate) fn len(&self) -> usize {
+        self.levels.iter().map(VecDeque::len).sum()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.levels.iter().all(VecDeque::is_empty)
+    }
+
+    pub(crate) fn push(&mut self, task: Arc<Task>) {
+        self.levels[task.priority().level()].push_back((self.popped, task));
+    }
+
+    /// Pushes a task that yielded: it runs again first while it has credit left from its shares,
+    /// see [`CoroutineBuilder::shares`](crate::CoroutineBuilder::shares)
+    pub(crate) fn push_yielded(&mut self, task: Arc<Task>) {
+        if task.consume_credit() {
+            self.levels[task.priority().level()].push_front((self.popped, task));
+        } else {
+            self.push(task);
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Arc<Task>> {
+        let popped = self.popped;
+        let aged = self.levels.iter().position(|level| {
+            level
+                .front()
+                .is_some_and(|(queued, _)| popped - queued >= AGING)
+        });
+        let level = aged.or_else(|| self.levels.iter().rposition(|level| !level.is_empty()))?;
+        self.popped += 1;
+        self.levels[level].pop_front().map(|(_, task)| task)
+    }
+
+    /// Removes up to half of the tasks, only the `stealable` ones, from the back of the queues
+    /// of the highest priorities first. The tasks are returned back to front.
+    pub(crate) fn steal(&mut self, stealable: impl Fn(&Task) -> bool) -> Vec<Arc<Task>> {
+        let max = self.len().div_ceil(2);
+        let mut stolen = Vec::new();
+        for level in self.levels.iter_mut().rev() {
+            let mut i = level.len();
+            while i > 0 && stolen.len() < max {
+                i -= 1;
+                if stealable(&level[i].1) {
+                    stolen.extend(level.remove(i).map(|(_, task)| task));
+                }
+            }
+        }
+        stolen
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for level in &mut self.levels {
+            level.clear();
+        }
+    }
+}
+
+impl Extend<Arc<Task>> for RunQueue {
+    fn extend<I: IntoIterator<Item = Arc<Task>>>(&mut self, iter: I) {
+        for task in iter {
+            self.push(task);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{yield_now, CoroutineBuilder};
+
+    #[test]
+    fn test_priorities() {
+        crate::run(|| {
+            let order = Rc::new(RefCell::new(Vec::new()));
+            let handles: Vec<_> = [Priority::Low, Priority::Normal, Priority::High]
+                .into_iter()
+                .map(|priority| {
+                    let order = order.clone();
+                    CoroutineBuilder::new()
+                        .priority(priority)
+                        .spawn(move || order.borrow_mut().push(priority))
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(
+                *order.borrow(),
+                [Priority::High, Priority::Normal, Priority::Low]
+            );
+        });
+    }
+
+    #[test]
+    fn test_aging_and_shares() {
+        crate::run(|| {
+            let runs = Rc::new(RefCell::new([0; 3]));
+            let spin = |priority: Priority, shares: u32, index: usize| {
+                let runs = runs.clone();
+                CoroutineBuilder::new()
+                    .priority(priority)
+                    .shares(shares)
+                    .spawn(move || {
+                        for _ in 0..100 {
+                            runs.borrow_mut()[index] += 1;
+                            yield_now();
+                        }
+                    })
+            };
+            let low = spin(Priority::Low, 1, 0);
+            let high = spin(Priority::High, 1, 1);
+            let heavy = spin(Priority::High, 3, 2);
+            // The main coroutine waits behind the high priority coroutines too
+            yield_now();
+            let [low_runs, high_runs, heavy_runs] = *runs.borrow();
+            // The low priority coroutine was not starved
+            assert!(low_runs > 0);
+            assert!(heavy_runs >= 2 * high_runs, "{} {}", heavy_runs, high_runs);
+            for handle in [low, high, heavy] {
+                handle.join().unwrap();
+            }
+        });
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    cell::RefCell,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{
+    info::ParkReason,
+    waiter::{WaitQueue, Waiter},
+    worker,
+};
+
+/// The epoll token of the worker waker
+const WAKER_TOKEN: u64 = 0;
+
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Readiness interest of a coroutine blocked on an I/O object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Interest(usize);
+
+impl Interest {
+    pub(crate) const READABLE: Interest = Interest(READABLE);
+    pub(crate) const WRITABLE: Interest = Interest(WRITABLE);
+
+    /// Readiness bits satisfying this interest
+    #[inline(always)]
+    fn mask(self) -> usize {
+        let mut mask = self.0;
+        if (self.0 & READABLE) != 0 {
+            mask |= READ_CLOSED;
+        }
+        if (self.0 & WRITABLE) != 0 {
+            mask |= WRITE_CLOSED;
+        }
+        mask
+    }
+}
+
+const READABLE: usize = 1usize << 0;
+const WRITABLE: usize = 1usize << 1;
+const READ_CLOSED: usize = 1usize << 2;
+const WRITE_CLOSED: usize = 1usize << 3;
+const READINESS_MASK: usize = READABLE | WRITABLE | READ_CLOSED | WRITE_CLOSED;
+/// The upper bits of the readiness count the dispatched events
+const TICK_SHIFT: u32 = 16;
+
+fn readiness_from_epoll(events: u32) -> usize {
+    let events = events as libc::c_int;
+    let mut readiness = 0;
+    if (events & (libc::EPOLLIN | libc::EPOLLPRI)) != 0 {
+        readiness |= READABLE;
+    }
+    if (events & libc::EPOLLOUT) != 0 {
+        readiness |= WRITABLE;
+    }
+    if (events & (libc::EPOLLRDHUP | libc::EPOLLHUP | libc::EPOLLERR)) != 0 {
+        readiness |= READ_CLOSED;
+    }
+    if (events & (libc::EPOLLHUP | libc::EPOLLERR)) != 0 {
+        readiness |= WRITE_CLOSED;
+    }
+    readiness
+}
+
+#[derive(Default)]
+struct Waiters {
+    readers: WaitQueue,
+    writers: WaitQueue,
+}
+
+impl Waiters {
+    fn queue(&mut self, interest: Interest) -> &mut WaitQueue {
+        if interest == Interest::READABLE {
+            &mut self.readers
+        } else {
+            &mut self.writers
+        }
+    }
+}
+
+/// The readiness state of a registered file descriptor
+pub(crate) struct ScheduledIo {
+    readiness: AtomicUsize,
+    waiters: Mutex<Waiters>,
+}
+
+impl ScheduledIo {
+    fn new() -> Self {
+        Self {
+            readiness: AtomicUsize::new(0),
+            waiters: Mutex::new(Waiters::default()),
+        }
+    }
+
+    fn dispatch(&self, events: u32) {
+        let ready = readiness_from_epoll(events);
+        let _ = self
+            .readiness
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                let tick = (current >> TICK_SHIFT).wrapping_add(1);
+                Some((tick << TICK_SHIFT) | (current & READINESS_MASK) | ready)
+            });
+        let mut waiters = self.waiters.lock().unwrap();
+        if (ready & Interest::READABLE.mask()) != 0 {
+            waiters.readers.notify_all();
+        }
+        if (ready & Interest::WRITABLE.mask()) != 0 {
+            waiters.writers.notify_all();
+        }
+    }
+
+    /// Clears the readiness observed in `snapshot` unless an event was dispatched since
+    fn clear_readiness(&self, snapshot: usize, interest: Interest) {
+        let _ = self.readiness.compare_exchange(
+            snapshot,
+            snapshot & !(interest.0 & (READABLE | WRITABLE)),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+
+    #[inline(always)]
+    fn is_ready(&self, interest: Interest) -> bool {
+        (self.readiness.load(Ordering::Acquire) & interest.mask()) != 0
+    }
+
+    /// Queues `waiter` until the readiness matches `interest`, returns `false` when it already
+    /// does
+    fn register(&self, interest: Interest, waiter: &Arc<Waiter>) -> bool {
+        let mut waiters = self.waiters.lock().unwrap();
+        // The reactor updates the readiness before taking the lock
+        if self.is_ready(interest) {
+            return false;
+        }
+        waiters.queue(interest).push(waiter.clone());
+        true
+    }
+
+    fn unregister(&self, interest: Interest, waiter: &Arc<Waiter>) {
+        self.waiters.lock().unwrap().queue(interest).remove(waiter);
+    }
+
+    /// Suspends the current coroutine until the readiness matches `interest`, fails with
+    /// `TimedOut` once the deadline of the enclosing `timeout` scope is reached and with
+    /// `Cancelled` when the coroutine is cancelled
+    fn wait(&self, interest: Interest) -> io::Result<()> {
+        loop {
+            if self.is_ready(interest) {
+                return Ok(());
+            }
+            let waiter = Waiter::new(ParkReason::Io);
+            if !self.register(interest, &waiter) {
+                return Ok(());
+            }
+            if let Err(interrupt) = waiter.try_wait_deadline(None) {
+                self.unregister(interest, &waiter);
+                return Err(interrupt.into_io_error());
+            }
+        }
+    }
+}
+
+/// An epoll instance shared by a worker and the I/O objects registered on it
+pub(crate) struct Selector {
+    epfd: OwnedFd,
+    /// Deregistered I/O states, kept alive until the next poll as pending events may point to them
+    released: Mutex<Vec<Arc<ScheduledIo>>>,
+}
+
+impl Selector {
+    fn ctl(&self, op: libc::c_int, fd: RawFd, events: libc::c_int, token: u64) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: events as u32,
+            u64: token,
+        };
+        if unsafe { libc::epoll_ctl(self.epfd.as_raw_fd(), op, fd, &mut event) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Wakes up a worker blocked in `Reactor::poll` from any thread
+pub(crate) struct Waker {
+    fd: OwnedFd,
+}
+
+impl Waker {
+    pub(crate) fn wake(&self) {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &one as *const u64 as _,
+                std::mem::size_of::<u64>(),
+            )
+        };
+    }
+
+    /// Returns the eventfd of the waker, for the other sources of wakeups
+    #[inline(always)]
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    fn reset(&self) {
+        let mut count: u64 = 0;
+        unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut count as *mut u64 as _,
+                std::mem::size_of::<u64>(),
+            )
+        };
+    }
+}
+
+/// The per-worker I/O event loop
+pub(crate) struct Reactor {
+    selector: Arc<Selector>,
+    waker: Arc<Waker>,
+    events: RefCell<Vec<libc::epoll_event>>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<Self> {
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epfd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let selector = Arc::new(Selector {
+            epfd: unsafe { OwnedFd::from_raw_fd(epfd) },
+            released: Mutex::new(Vec::new()),
+        });
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let waker = Arc::new(Waker {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        });
+        selector.ctl(libc::EPOLL_CTL_ADD, fd, libc::EPOLLIN, WAKER_TOKEN)?;
+        Ok(Self {
+            selector,
+            waker,
+            events: RefCell::new(Vec::with_capacity(EVENTS_CAPACITY)),
+        })
+    }
+
+    /// Returns the waker of this reactor
+    pub(crate) fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    /// Waits up to `timeout`, or forever when `None`, for I/O events and unparks the coroutines
+    /// waiting for them. Returns the number of events, 0 when interrupted by a signal.
+    pub(crate) fn poll(&self, timeout: Option<Duration>) -> io::Result<usize> {
+        self.selector.released.lock().unwrap().clear();
+        let timeout_ms = match timeout {
+            None => -1,
+            Some(timeout) => {
+                // Rounds up so that a pending deadline is never polled in a busy-loop
+                let ms = timeout.as_nanos().div_ceil(1_000_000);
+                ms.min(libc::c_int::MAX as u128) as libc::c_int
+            }
+        };
+        let mut events = self.events.borrow_mut();
+        let n = unsafe {
+            libc::epoll_wait(
+                self.selector.epfd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.capacity() as libc::c_int,
+                timeout_ms,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(0)
+            } else {
+                Err(err)
+            };
+        }
+        unsafe { events.set_len(n as usize) };
+        for event in events.iter() {
+            let (token, flags) = (event.u64, event.events);
+            if token == WAKER_TOKEN {
+                self.waker.reset();
+            } else {
+                let io = unsafe { &*(token as usize as *const ScheduledIo) };
+                io.dispatch(flags);
+            }
+        }
+        events.clear();
+        Ok(n as usize)
+    }
+}
+
+/// The registration of a file descriptor in the reactor of the current worker
+pub(crate) struct Registration {
+    selector: Arc<Selector>,
+    io: Arc<ScheduledIo>,
+    fd: RawFd,
+}
+
+impl Registration {
+    /// Registers `fd` in the reactor of the current worker
+    ///
+    /// The file descriptor **MUST** stay open until the registration is dropped.
+    pub(crate) fn new(fd: RawFd) -> io::Result<Self> {
+        let worker = worker::current().ok_or_else(|| {
+            io::Error::other("I/O objects must be created inside a coroutine runtime")
+        })?;
+        let selector = worker.reactor().selector.clone();
+        let io = Arc::new(ScheduledIo::new());
+        selector.ctl(
+            libc::EPOLL_CTL_ADD,
+            fd,
+            libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET,
+            Arc::as_ptr(&io) as usize as u64,
+        )?;
+        Ok(Self { selector, io, fd })
+    }
+
+    /// Runs the non-blocking operation `f` until it does not fail with `WouldBlock`, parking the
+    /// current coroutine until the readiness matches `interest` in-between
+    pub(crate) fn do_io<R>(
+        &self,
+        interest: Interest,
+        mut f: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        worker::preemption_point();
+        loop {
+            let snapshot = self.io.readiness.load(Ordering::Acquire);
+            match f() {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_readiness(snapshot, interest);
+                    self.io.wait(interest)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Parks the current coroutine until the readiness matches `interest`
+    pub(crate) fn readiness(&self, interest: Interest) -> io::Result<()> {
+        self.io.wait(interest)
+    }
+
+    /// Returns `true` when the last readiness seen matches `interest`, never suspends
+    pub(crate) fn is_ready(&self, interest: Interest) -> bool {
+        self.io.is_ready(interest)
+    }
+
+    /// Queues `waiter` to be notified once the readiness matches `interest`
+    pub(crate) fn register(&self, interest: Interest, waiter: &Arc<Waiter>) {
+        if !self.io.register(interest, waiter) {
+            waiter.notify();
+        }
+    }
+
+    pub(crate) fn unregister(&self, interest: Interest, waiter: &Arc<Waiter>) {
+        self.io.unregister(interest, waiter);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = self.selector.ctl(libc::EPOLL_CTL_DEL, self.fd, 0, 0);
+        self.selector.released.lock().unwrap().push(self.io.clone());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    io,
+    mem::{self, MaybeUninit},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    thread::{self, ThreadId},
+};
+
+use super::{watchdog, worker, Interest};
+use crate::io::PollEvented;
+
+/// Signals raised by faults (and the uncatchable ones), never redirected to a signalfd so that
+/// fault handlers like stack overflow detection keep working
+const SYNCHRONOUS: [libc::c_int; 6] = [
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGILL,
+    libc::SIGFPE,
+    libc::SIGKILL,
+    libc::SIGSTOP,
+];
+
+/// Upper bound of the signal numbers
+const NSIG: usize = 65;
+
+struct Mask {
+    blocked: libc::sigset_t,
+    /// The mask of the worker thread before the first signal was blocked
+    previous: libc::sigset_t,
+    thread: ThreadId,
+}
+
+/// The signals received by a worker through a signalfd
+///
+/// The signals are blocked on the worker thread as they are requested, and unblocked when the
+/// worker is dropped.
+pub(crate) struct Signals {
+    fd: OnceCell<PollEvented<OwnedFd>>,
+    mask: RefCell<Option<Mask>>,
+    /// Signals read from the signalfd but not received yet
+    pending: RefCell<[u32; NSIG]>,
+}
+
+impl Signals {
+    pub(crate) fn new() -> Self {
+        Self {
+            fd: OnceCell::new(),
+            mask: RefCell::new(None),
+            pending: RefCell::new([0; NSIG]),
+        }
+    }
+
+    /// Suspends the current coroutine until `signal` is received
+    pub(crate) fn recv(&self, signal: libc::c_int) -> io::Result<()> {
+        if signal <= 0
+            || signal as usize >= NSIG
+            || SYNCHRONOUS.contains(&signal)
+            || signal == watchdog::capture_signal()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Signal {} cannot be received", signal),
+            ));
+        }
+        // The signal is blocked on the thread of this worker only
+        let task = worker::current_task();
+        let _pin = task.pin();
+        let fd = self.add(signal)?;
+        fd.do_io(Interest::READABLE, |fd| {
+            self.drain(fd)?;
+            let mut pending = self.pending.borrow_mut();
+            match &mut pending[signal as usize] {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                count => {
+                    *count -= 1;
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Blocks `signal` and adds it to the signalfd
+    fn add(&self, signal: libc::c_int) -> io::Result<&PollEvented<OwnedFd>> {
+        let mut mask = self.mask.borrow_mut();
+        let mask = match &mut *mask {
+            Some(mask) => mask,
+            None => mask.insert(Mask {
+                blocked: empty_set(),
+                previous: empty_set(),
+                thread: thread::current().id(),
+            }),
+        };
+        if unsafe { libc::sigismember(&mask.blocked, signal) } == 1 {
+            if let Some(fd) = self.fd.get() {
+                return Ok(fd);
+            }
+        }
+        let mut blocked = mask.blocked;
+        unsafe { libc::sigaddset(&mut blocked, signal) };
+        let first = self.fd.get().is_none();
+        let previous: *mut libc::sigset_t = if first {
+            &mut mask.previous
+        } else {
+            std::ptr::null_mut()
+        };
+        check(unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, previous) })?;
+        let raw = self.fd.get().map_or(-1, |fd| fd.get_ref().as_raw_fd());
+        let res = unsafe { libc::signalfd(raw, &blocked, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        mask.blocked = blocked;
+        if first {
+            let fd = PollEvented::new(unsafe { OwnedFd::from_raw_fd(res) })?;
+            let _ = self.fd.set(fd);
+        }
+        Ok(self.fd.get().unwrap())
+    }
+
+    /// Reads every signal queued on the signalfd
+    fn drain(&self, fd: &OwnedFd) -> io::Result<()> {
+        let mut pending = self.pending.borrow_mut();
+        loop {
+            let mut info = MaybeUninit::<libc::signalfd_siginfo>::uninit();
+            let size = mem::size_of::<libc::signalfd_siginfo>();
+            let res = unsafe { libc::read(fd.as_raw_fd(), info.as_mut_ptr().cast(), size) };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::WouldBlock => Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(err),
+                };
+            }
+            let info = unsafe { info.assume_init() };
+            if let Some(count) = pending.get_mut(info.ssi_signo as usize) {
+                *count += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        if let Some(mask) = self.mask.get_mut() {
+            // The mask is per-thread, it cannot be restored from another thread
+            if self.fd.get().is_some() && mask.thread == thread::current().id() {
+                unsafe {
+                    libc::pthread_sigmask(libc::SIG_SETMASK, &mask.previous, std::ptr::null_mut())
+                };
+            }
+        }
+    }
+}
+
+fn empty_set() -> libc::sigset_t {
+    let mut set = MaybeUninit::uninit();
+    unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        set.assume_init()
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(res))
+    }
+}
+
+/// Blocks every asynchronous signal on the calling thread
+///
+/// For the helper threads of the runtime: a process-directed signal is delivered to any thread
+/// not blocking it, which would steal it from the signalfd of the workers.
+pub(crate) fn block_async_signals() {
+    let mut set = MaybeUninit::uninit();
+    unsafe {
+        libc::sigfillset(set.as_mut_ptr());
+        let mut set = set.assume_init();
+        for signal in SYNCHRONOUS {
+            libc::sigdelset(&mut set, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+    }
+}
+
+/// Unblocks `signal` on the calling thread
+pub(crate) fn unblock(signal: libc::c_int) {
+    let mut set = empty_set();
+    unsafe {
+        libc::sigaddset(&mut set, signal);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+    }
+}
@@ -0,0 +1,666 @@
+use std::{
+    any::Any,
+    cell::{Cell, UnsafeCell},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::Instant,
+};
+
+use ucontext::UContext;
+
+use super::{
+    cancel::{self, CancelToken, Cancelled, Interrupt},
+    deadlock::Blocker,
+    info::{CoroutineId, CoroutineInfo, CoroutineState, ParkReason},
+    queue::Priority,
+    waiter::{WaitQueue, Waiter},
+    worker,
+};
+use crate::scope::{Scope, ScopedJoinHandle};
+
+/// The task is in a run queue
+const SCHEDULED: u8 = 0;
+/// The task is running (or about to park)
+const RUNNING: u8 = 1;
+/// The task is suspended, waiting for an `unpark`
+const PARKED: u8 = 2;
+/// The task was unparked while running, the next `park` returns immediately
+const NOTIFIED: u8 = 3;
+/// The task returned
+const DONE: u8 = 4;
+
+/// The task was not cancelled
+const CANCEL_NONE: u8 = 0;
+/// The task was cancelled, the coroutine was not told yet
+const CANCEL_REQUESTED: u8 = 1;
+/// The coroutine got a `Cancelled` error, it is unwound at its next suspension
+const CANCEL_DELIVERED: u8 = 2;
+
+/// A coroutine owned by a worker.
+///
+/// A task may be unparked from any thread but its context is only ever touched by the thread of
+/// its owning worker. A movable task changes owner when another worker steals it from its run
+/// queue, see `Worker::steal`.
+pub(crate) struct Task {
+    id: CoroutineId,
+    name: Option<Arc<str>>,
+    stack_size: usize,
+    priority: Priority,
+    shares: u32,
+    /// Consecutive runs left after yielding, only accessed from the owning worker
+    credit: Cell<u32>,
+    state: AtomicU8,
+    cancel: AtomicU8,
+    ctx: UnsafeCell<Option<UContext>>,
+    /// The deadline of the innermost `timeout` scope
+    deadline: Cell<Option<Instant>>,
+    /// What the task waits for while parked
+    park_reason: Cell<Option<ParkReason>>,
+    /// The primitive the task waits on while parked
+    blocker: Cell<Option<Blocker>>,
+    /// Entered while the coroutine runs
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    /// Spawned by `spawn_movable`, it may be stolen by another worker
+    movable: bool,
+    /// The coroutine relies on the state of its worker while it is suspended (e.g. a timer)
+    pins: Cell<u32>,
+    /// Set while a worker runs on the context, until it swapped back to the worker
+    resumed: AtomicBool,
+    /// The owning worker, replaced when the task is stolen
+    worker: Mutex<Arc<worker::Shared>>,
+}
+
+// Safety: `ctx`, `credit`, `deadline`, `park_reason`, `blocker` and `pins` are only accessed
+// from the thread of the owning worker, which only hands them over to a thief once the context
+// swapped out, everything else is thread-safe
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    pub(crate) fn new(
+        ctx: UContext,
+        builder: CoroutineBuilder,
+        worker: Arc<worker::Shared>,
+    ) -> Arc<Self> {
+        let movable = ctx.is_movable();
+        let CoroutineBuilder {
+            name,
+            priority,
+            shares,
+        } = builder;
+        let id = CoroutineId::from_context(ctx.id());
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!("coroutine", id = id.as_u64(), name = name.as_deref());
+        Arc::new(Self {
+            id,
+            name,
+            priority,
+            shares,
+            credit: Cell::new(shares),
+            stack_size: ctx.stack_size(),
+            state: AtomicU8::new(SCHEDULED),
+            cancel: AtomicU8::new(CANCEL_NONE),
+            ctx: UnsafeCell::new(Some(ctx)),
+            deadline: Cell::new(None),
+            park_reason: Cell::new(None),
+            blocker: Cell::new(None),
+            #[cfg(feature = "tracing")]
+            span,
+            movable,
+            pins: Cell::new(0),
+            resumed: AtomicBool::new(false),
+            worker: Mutex::new(worker),
+        })
+    }
+
+    #[inline(always)]
+    pub(crate) fn id(&self) -> CoroutineId {
+        self.id
+    }
+
+    #[inline(always)]
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    #[inline(always)]
+    pub(crate) fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns `true` when the task may run again right after yielding, resets its credit to its
+    /// shares otherwise
+    pub(crate) fn consume_credit(&self) -> bool {
+        match self.credit.get() {
+            credit if credit > 1 => {
+                self.credit.set(credit - 1);
+                true
+            }
+            _ => {
+                self.credit.set(self.shares);
+                false
+            }
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[inline(always)]
+    pub(crate) fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    #[inline(always)]
+    pub(crate) fn blocker(&self) -> Option<Blocker> {
+        self.blocker.get()
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_blocker(&self, blocker: Option<Blocker>) {
+        self.blocker.set(blocker);
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_park_reason(&self, reason: Option<ParkReason>) {
+        self.park_reason.set(reason);
+    }
+
+    /// Returns a snapshot of the task, only valid on the owning worker
+    pub(crate) fn info(&self) -> CoroutineInfo {
+        let state = match (self.state.load(Ordering::Acquire), self.park_reason.get()) {
+            (SCHEDULED, _) => CoroutineState::Runnable,
+            (PARKED, Some(reason)) => CoroutineState::Parked(reason),
+            (DONE, _) => CoroutineState::Done,
+            _ => CoroutineState::Running,
+        };
+        CoroutineInfo {
+            id: self.id,
+            name: self.name.clone(),
+            state,
+            priority: self.priority,
+            stack_size: self.stack_size,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_done(&self) -> bool {
+        self.state.load(Ordering::Acquire) == DONE
+    }
+
+    /// Returns the deadline of the innermost `timeout` scope, only valid on the owning worker
+    #[inline(always)]
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.deadline.get()
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_deadline(&self, deadline: Option<Instant>) {
+        self.deadline.set(deadline);
+    }
+
+    /// Makes the task runnable again, may be called from any thread
+    pub(crate) fn unpark(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                PARKED => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self
+                .state
+                .compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    if next == SCHEDULED {
+                        let worker = self.worker.lock().unwrap().clone();
+                        worker.schedule(self.clone());
+                    }
+                    return;
+                }
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    /// Requests the cancellation of the task and wakes it up, returns `false` when it already
+    /// finished
+    pub(crate) fn cancel(self: &Arc<Self>) -> bool {
+        if self.is_done() {
+            return false;
+        }
+        let _ = self.cancel.compare_exchange(
+            CANCEL_NONE,
+            CANCEL_REQUESTED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        self.unpark();
+        true
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Acquire) != CANCEL_NONE
+    }
+
+    /// Tells the cancelled coroutine it was cancelled: returns the error the first time, unwinds
+    /// the coroutine afterwards
+    pub(crate) fn deliver_cancel(&self) -> Cancelled {
+        match self.cancel.swap(CANCEL_DELIVERED, Ordering::AcqRel) {
+            CANCEL_REQUESTED => Cancelled,
+            _ => cancel::unwind(),
+        }
+    }
+
+    /// Marks a scheduled task as running, called by the worker right before resuming it
+    #[inline(always)]
+    pub(crate) fn set_running(&self) {
+        self.state.store(RUNNING, Ordering::Release);
+    }
+
+    /// Marks the running task as scheduled, called right before pushing it back to a run queue
+    #[inline(always)]
+    pub(crate) fn set_scheduled(&self) {
+        self.state.store(SCHEDULED, Ordering::Release);
+    }
+
+    /// Marks the running task as done, the worker releases its context once it swapped out
+    #[inline(always)]
+    pub(crate) fn set_done(&self) {
+        self.state.store(DONE, Ordering::Release);
+    }
+
+    /// Returns `true` when the running task must swap out to the scheduler, `false` when it was
+    /// notified in the meantime and must keep running
+    pub(crate) fn try_park(&self) -> bool {
+        match self
+            .state
+            .compare_exchange(RUNNING, PARKED, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => true,
+            Err(NOTIFIED) => {
+                self.state.store(RUNNING, Ordering::Release);
+                false
+            }
+            Err(state) => panic!("Invalid coroutine state on park: {}", state),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_movable(&self) -> bool {
+        self.movable
+    }
+
+    /// Keeps the running task on its worker until the guard is dropped
+    pub(crate) fn pin(&self) -> Pinned<'_> {
+        self.pins.set(self.pins.get() + 1);
+        Pinned(self)
+    }
+
+    /// Records whether a worker runs on the context, called by the worker around the swaps
+    #[inline(always)]
+    pub(crate) fn set_resumed(&self, resumed: bool) {
+        self.resumed.store(resumed, Ordering::Release);
+    }
+
+    /// Returns `true` when another worker may resume the task: it is movable, not pinned, and
+    /// its context swapped out. Only valid under the run queue lock of the owning worker.
+    pub(crate) fn is_stealable(&self) -> bool {
+        // The pins are published by the worker clearing `resumed`
+        self.movable && !self.resumed.load(Ordering::Acquire) && self.pins.get() == 0
+    }
+
+    /// Moves the context of a stolen task to the worker of the current thread, which resumes
+    /// it from `root`
+    ///
+    /// # Safety
+    ///  - The task must be stealable and removed from the run queue and the tasks of its
+    ///    previous worker
+    pub(crate) unsafe fn migrate(&self, root: &UContext, worker: Arc<worker::Shared>) {
+        let slot = unsafe { &mut *self.ctx.get() };
+        let ctx = slot.take().expect("Stole a released coroutine");
+        // Safety: `spawn_movable` requires the values living on the stack to be `Send`
+        let mut ctx = match unsafe { ctx.into_movable() } {
+            Ok(ctx) => ctx.migrate(),
+            Err(_) => unreachable!("Stole a pinned or running coroutine"),
+        };
+        ctx.set_exit_context(Some(root));
+        *slot = Some(ctx);
+        *self.worker.lock().unwrap() = worker;
+    }
+
+    /// Returns the coroutine context
+    ///
+    /// # Safety
+    ///  - Must be called from the thread of the owning worker
+    ///  - The returned reference must not outlive a swap
+    #[allow(clippy::mut_from_ref)]
+    #[inline(always)]
+    pub(crate) unsafe fn context(&self) -> Option<&mut UContext> {
+        unsafe { (*self.ctx.get()).as_mut() }
+    }
+
+    /// Releases the coroutine context (and its stack)
+    ///
+    /// # Safety
+    ///  - Must be called from the thread of the owning worker
+    ///  - The task **MUST NOT** be running
+    pub(crate) unsafe fn release(&self) {
+        self.state.store(DONE, Ordering::Release);
+        drop(unsafe { (*self.ctx.get()).take() });
+    }
+}
+
+/// Keeps a task on its worker while alive, see [`Task::pin`]
+pub(crate) struct Pinned<'a>(&'a Task);
+
+impl Drop for Pinned<'_> {
+    fn drop(&mut self) {
+        self.0.pins.set(self.0.pins.get() - 1);
+    }
+}
+
+struct PacketState<T> {
+    result: Option<thread::Result<T>>,
+    joiners: WaitQueue,
+    /// The waker of the future awaiting the `JoinHandle`
+    waker: Option<Waker>,
+    finished: bool,
+}
+
+/// Where a coroutine stores its result for its `JoinHandle`
+pub(crate) struct Packet<T> {
+    state: Mutex<PacketState<T>>,
+    /// The coroutine storing the result, 0 for a blocking call
+    owner: AtomicU64,
+}
+
+impl<T> Packet<T> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(PacketState {
+                result: None,
+                joiners: WaitQueue::new(),
+                waker: None,
+                finished: false,
+            }),
+            owner: AtomicU64::new(0),
+        })
+    }
+
+    /// Records the coroutine storing the result, reported as the holder of the packet
+    pub(crate) fn set_owner(&self, owner: CoroutineId) {
+        self.owner.store(owner.as_u64(), Ordering::Relaxed);
+    }
+
+    /// # Safety
+    ///  - `packet` must point to a live `Packet<T>`
+    unsafe fn owner(packet: *const ()) -> Option<CoroutineId> {
+        let packet = unsafe { &*(packet as *const Self) };
+        CoroutineId::from_u64(packet.owner.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn complete(&self, result: thread::Result<T>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        state.finished = true;
+        state.joiners.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().finished
+    }
+
+    /// Takes the result of a finished coroutine
+    pub(crate) fn take(&self) -> Option<thread::Result<T>> {
+        self.state.lock().unwrap().result.take()
+    }
+
+    /// Takes the panic payload of a finished coroutine, leaves a successful result in place
+    pub(crate) fn take_panic(&self) -> Option<Box<dyn Any + Send>> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Err(payload)) => Some(payload),
+            result => {
+                state.result = result;
+                None
+            }
+        }
+    }
+
+    /// Queues a waiter notified when the coroutine finishes, returns `None` when it finished
+    fn register(&self, reason: ParkReason) -> Option<Arc<Waiter>> {
+        // Safety: the joiner borrows the packet while it waits
+        let waiter = Waiter::blocked_on(reason, unsafe { Blocker::held(self, Self::owner) });
+        let mut state = self.state.lock().unwrap();
+        if state.finished {
+            return None;
+        }
+        state.joiners.push(waiter.clone());
+        Some(waiter)
+    }
+
+    /// Parks the current coroutine until the coroutine finished, fails when the current
+    /// coroutine is cancelled or reaches the deadline of its `timeout` scope first
+    pub(crate) fn try_wait_finished(&self, reason: ParkReason) -> Result<(), Interrupt> {
+        while let Some(waiter) = self.register(reason) {
+            waiter.try_wait_deadline(None)?;
+        }
+        Ok(())
+    }
+
+    /// Parks the current coroutine until the coroutine finished, even when the current
+    /// coroutine is cancelled
+    pub(crate) fn wait_finished_uncancellable(&self, reason: ParkReason) {
+        while let Some(waiter) = self.register(reason) {
+            waiter.wait_uncancellable();
+        }
+    }
+
+    /// Returns the result once the coroutine finished, registers the waker of `cx` otherwise
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<thread::Result<T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.finished {
+            return Poll::Ready(
+                state
+                    .result
+                    .take()
+                    .expect("The result of the coroutine was already taken"),
+            );
+        }
+        if !state
+            .waker
+            .as_ref()
+            .is_some_and(|waker| waker.will_wake(cx.waker()))
+        {
+            state.waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    /// Parks the current coroutine until the result is available
+    pub(crate) fn wait(&self, reason: ParkReason) -> thread::Result<T> {
+        if let Err(interrupt) = self.try_wait_finished(reason) {
+            interrupt.unwind();
+        }
+        self.take()
+            .expect("The result of the coroutine was already taken")
+    }
+}
+
+/// Coroutine configuration, the [`spawn`](crate::spawn) functions use the default one
+#[derive(Debug, Clone)]
+pub struct CoroutineBuilder {
+    name: Option<Arc<str>>,
+    priority: Priority,
+    shares: u32,
+}
+
+impl Default for CoroutineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoroutineBuilder {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            priority: Priority::Normal,
+            shares: 1,
+        }
+    }
+
+    /// Names the coroutine, for [`dump`](crate::dump) and the logs
+    pub fn name<S: Into<Arc<str>>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the scheduling priority of the coroutine, [`Priority::Normal`] by default
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the CPU shares of the coroutine among the coroutines of its priority, 1 by default
+    ///
+    /// A coroutine with `n` shares is resumed up to `n` times in a row when it yields, getting
+    /// `n` times the CPU time of a coroutine with one share when both keep yielding. Shares are
+    /// only balanced between the coroutines of the same worker, which share its thread:
+    /// splitting CPU time between threads is left to the OS scheduler.
+    ///
+    /// # Panics
+    ///  - When `shares` is 0
+    pub fn shares(mut self, shares: u32) -> Self {
+        assert!(shares > 0, "A coroutine needs at least one share");
+        self.shares = shares;
+        self
+    }
+
+    /// Spawns the coroutine on the current runtime, see [`spawn`](crate::spawn)
+    ///
+    /// # Panics
+    ///  - When called outside of a runtime
+    ///  - When the coroutine stack cannot be allocated
+    pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        worker::current()
+            .expect("`spawn` called outside of a coroutine runtime")
+            .spawn(self, f)
+    }
+
+    /// Spawns a coroutine which may be stolen by the other workers, see
+    /// [`spawn_movable`](crate::spawn_movable)
+    ///
+    /// # Safety
+    ///  - See [`spawn_movable`](crate::spawn_movable)
+    ///
+    /// # Panics
+    ///  - When called outside of a runtime
+    ///  - When the coroutine stack cannot be allocated
+    pub unsafe fn spawn_movable<F, T>(self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        worker::current()
+            .expect("`spawn_movable` called outside of a coroutine runtime")
+            .spawn_movable(self, f)
+    }
+
+    /// Spawns the coroutine in `scope`, see [`Scope::spawn`]
+    ///
+    /// # Panics
+    ///  - When called outside of a runtime
+    ///  - When the coroutine stack cannot be allocated
+    pub fn spawn_scoped<'scope, 'env, F, T>(
+        self,
+        scope: &'scope Scope<'scope, 'env>,
+        f: F,
+    ) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        scope.spawn_with(self, f)
+    }
+}
+
+/// An owned permission to join on a coroutine (block on its termination).
+pub struct JoinHandle<T> {
+    packet: Arc<Packet<T>>,
+    task: Arc<Task>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(packet: Arc<Packet<T>>, task: Arc<Task>) -> Self {
+        Self { packet, task }
+    }
+
+    /// Suspends the current coroutine until the joined coroutine returns.
+    ///
+    /// Returns `Err` with the panic payload when the coroutine panicked, the payload is
+    /// [`Cancelled`] when it was unwound by its cancellation.
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn join(self) -> thread::Result<T> {
+        self.packet.wait(ParkReason::Join)
+    }
+
+    /// Returns the identifier of the coroutine
+    pub fn id(&self) -> CoroutineId {
+        self.task.id()
+    }
+
+    /// Returns the name of the coroutine, see [`CoroutineBuilder::name`]
+    pub fn name(&self) -> Option<&str> {
+        self.task.name()
+    }
+
+    /// Returns `true` when the coroutine returned (or panicked)
+    pub fn is_finished(&self) -> bool {
+        self.packet.is_finished()
+    }
+
+    /// Cancels the coroutine, see [`Cancelled`]. Returns `true` when it was cancelled before it
+    /// finished.
+    pub fn cancel(&self) -> bool {
+        !self.packet.is_finished() && self.task.cancel()
+    }
+
+    /// Returns a token to cancel the coroutine without owning its handle
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken::new(self.task.clone())
+    }
+
+    pub(crate) fn packet(&self) -> &Arc<Packet<T>> {
+        &self.packet
+    }
+}
+
+/// Awaits the coroutine from async code, with the same output as [`join`](JoinHandle::join)
+///
+/// # Panics
+///  - When polled again after completion
+impl<T> Future for JoinHandle<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.packet.poll(cx)
+    }
+}
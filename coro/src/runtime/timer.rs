@@ -0,0 +1,90 @@
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+
+use super::task::Task;
+
+/// A registered timer, used to cancel it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimerId(u64);
+
+#[derive(Default)]
+struct Inner {
+    /// Deadlines ordered by expiration, cancelled timers are only removed once they reach the top
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// The coroutines to unpark, by timer id
+    pending: FxHashMap<u64, Arc<Task>>,
+    next_id: u64,
+}
+
+/// The per-worker timer store: a min-heap of deadlines with lazy cancellation
+#[derive(Default)]
+pub(crate) struct Timers {
+    inner: RefCell<Inner>,
+}
+
+impl Timers {
+    /// Unparks `task` once `deadline` is reached
+    pub(crate) fn insert(&self, deadline: Instant, task: Arc<Task>) -> TimerId {
+        let mut inner = self.inner.borrow_mut();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.heap.push(Reverse((deadline, id)));
+        inner.pending.insert(id, task);
+        TimerId(id)
+    }
+
+    pub(crate) fn remove(&self, timer: TimerId) {
+        let mut inner = self.inner.borrow_mut();
+        inner.pending.remove(&timer.0);
+        // Keeps the cancelled entries from piling up when most timers are cancelled
+        if inner.heap.len() > 64 && inner.heap.len() > 2 * inner.pending.len() {
+            let Inner { heap, pending, .. } = &mut *inner;
+            heap.retain(|Reverse((_, id))| pending.contains_key(id));
+        }
+    }
+
+    /// Returns the duration until the next deadline, `None` when there is no pending timer
+    pub(crate) fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let mut inner = self.inner.borrow_mut();
+        while let Some(Reverse((deadline, id))) = inner.heap.peek().copied() {
+            if inner.pending.contains_key(&id) {
+                return Some(deadline.saturating_duration_since(now));
+            }
+            inner.heap.pop();
+        }
+        None
+    }
+
+    /// Unparks the coroutines whose deadline is reached
+    pub(crate) fn fire(&self, now: Instant) {
+        let mut expired = Vec::new();
+        {
+            let mut inner = self.inner.borrow_mut();
+            while let Some(Reverse((deadline, id))) = inner.heap.peek().copied() {
+                if deadline > now {
+                    break;
+                }
+                inner.heap.pop();
+                if let Some(task) = inner.pending.remove(&id) {
+                    expired.push(task);
+                }
+            }
+        }
+        for task in expired {
+            task.unpark();
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.heap.clear();
+        inner.pending.clear();
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io, mem,
+    os::fd::{AsRawFd, RawFd},
+    sync::Arc,
+};
+
+use io_uring::{opcode, squeue, types, IoUring};
+use rustc_hash::FxHashMap;
+
+use super::{info::ParkReason, waiter::Waiter, worker};
+
+/// Number of submission queue entries of each ring
+const RING_ENTRIES: u32 = 256;
+
+/// The `user_data` of the cancellations, whose completions are ignored
+const CANCEL_TOKEN: u64 = u64::MAX;
+
+/// A file operation run by the io_uring of a worker
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum UringOp {
+    /// Reads into the buffer from the file position
+    Read,
+    /// Writes the buffer at the file position
+    Write,
+    /// Flushes the file to the storage device, only its data when `data_only`
+    Sync { data_only: bool },
+}
+
+/// An operation submitted to the kernel
+struct InFlight {
+    waiter: Arc<Waiter>,
+    /// The result of the completion, a negated errno on failure
+    result: Option<i32>,
+    /// Set when the coroutine stopped waiting, the operation is dropped once complete
+    abandoned: bool,
+    /// The file and the buffer used by the kernel until the operation completes
+    _file: Arc<File>,
+    buffer: Vec<u8>,
+}
+
+/// The io_uring of a worker, running the file operations of its coroutines
+///
+/// Completions are signaled on the eventfd waking the reactor of the worker, which hands them to
+/// the waiting coroutines after each poll.
+pub(crate) struct Uring {
+    ring: RefCell<IoUring>,
+    ops: RefCell<FxHashMap<u64, InFlight>>,
+    next_id: Cell<u64>,
+}
+
+impl Uring {
+    /// Returns a ring signaling its completions on `eventfd`, fails when the kernel does not
+    /// support io_uring (before Linux 5.6) or forbids it
+    pub(crate) fn new(eventfd: RawFd) -> io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        if !ring.params().is_feature_rw_cur_pos() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring does not support the file position",
+            ));
+        }
+        ring.submitter().register_eventfd(eventfd)?;
+        Ok(Self {
+            ring: RefCell::new(ring),
+            ops: RefCell::new(FxHashMap::default()),
+            next_id: Cell::new(0),
+        })
+    }
+
+    /// Runs `op` on `file` with `buffer`, the read destination or the written data, while the
+    /// current coroutine is suspended. Returns the number of bytes transferred and the buffer.
+    ///
+    /// Fails with `TimedOut` once the deadline of the enclosing `timeout` scope is reached and
+    /// with `Cancelled` when the coroutine is cancelled, the operation may still complete in the
+    /// background.
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub(crate) fn submit(
+        &self,
+        file: &Arc<File>,
+        op: UringOp,
+        mut buffer: Vec<u8>,
+    ) -> io::Result<(usize, Vec<u8>)> {
+        let fd = types::Fd(file.as_raw_fd());
+        let len = buffer.len().min(u32::MAX as usize) as u32;
+        let entry = match op {
+            UringOp::Read => opcode::Read::new(fd, buffer.as_mut_ptr(), len)
+                .offset(u64::MAX)
+                .build(),
+            UringOp::Write => opcode::Write::new(fd, buffer.as_ptr(), len)
+                .offset(u64::MAX)
+                .build(),
+            UringOp::Sync { data_only: false } => opcode::Fsync::new(fd).build(),
+            UringOp::Sync { data_only: true } => opcode::Fsync::new(fd)
+                .flags(types::FsyncFlags::DATASYNC)
+                .build(),
+        };
+        let task = worker::current_task();
+        // The completion is reaped by this worker
+        let _pin = task.pin();
+        let waiter = Waiter::new(ParkReason::Io);
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.ops.borrow_mut().insert(
+            id,
+            InFlight {
+                waiter: waiter.clone(),
+                result: None,
+                abandoned: false,
+                _file: file.clone(),
+                buffer,
+            },
+        );
+        if let Err(err) = self.push(&entry.user_data(id)) {
+            self.ops.borrow_mut().remove(&id);
+            return Err(err);
+        }
+        if let Err(interrupt) = waiter.try_wait_deadline(None) {
+            let mut ops = self.ops.borrow_mut();
+            let op = ops.get_mut(&id).unwrap();
+            if op.result.is_some() {
+                ops.remove(&id);
+            } else {
+                op.abandoned = true;
+                drop(ops);
+                let cancel = opcode::AsyncCancel::new(id).build();
+                let _ = self.push(&cancel.user_data(CANCEL_TOKEN));
+            }
+            return Err(interrupt.into_io_error());
+        }
+        let op = self.ops.borrow_mut().remove(&id).unwrap();
+        match op.result.expect("Notified before the completion") {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            res => Ok((res as usize, op.buffer)),
+        }
+    }
+
+    /// Queues `entry` and submits it to the kernel
+    fn push(&self, entry: &squeue::Entry) -> io::Result<()> {
+        let mut ring = self.ring.borrow_mut();
+        // Safety: the buffer and the file of the entry live in `ops` until it completes
+        while unsafe { ring.submission().push(entry) }.is_err() {
+            ring.submit()?;
+        }
+        ring.submit()?;
+        Ok(())
+    }
+
+    /// Hands the completed operations to their coroutines
+    pub(crate) fn complete(&self) {
+        let mut ring = self.ring.borrow_mut();
+        let mut ops = self.ops.borrow_mut();
+        for cqe in ring.completion() {
+            let id = cqe.user_data();
+            let Some(op) = ops.get_mut(&id) else {
+                continue;
+            };
+            if op.abandoned {
+                ops.remove(&id);
+            } else {
+                op.result = Some(cqe.result());
+                op.waiter.notify();
+            }
+        }
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        // The kernel writes to the buffers of the pending operations until they complete, their
+        // coroutines were released
+        for op in self.ops.get_mut().values_mut() {
+            op.abandoned = true;
+        }
+        while !self.ops.get_mut().is_empty() {
+            match self.ring.get_mut().submit_and_wait(1) {
+                Ok(_) => self.complete(),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::error!("Failed to wait for the pending file operations: {}", err);
+                    // Leaked, the kernel may still use them
+                    for (_, op) in self.ops.get_mut().drain() {
+                        mem::forget(op);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the io_uring of the current worker, `None` outside of a runtime or when the kernel
+/// does not support it
+pub(crate) fn current() -> Option<&'static Uring> {
+    worker::current()?.uring()
+}
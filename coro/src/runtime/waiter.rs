@@ -0,0 +1,196 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use super::{
+    cancel::Interrupt,
+    deadlock::Blocker,
+    info::{CoroutineId, ParkReason},
+    task::Task,
+    worker,
+};
+
+const WAITING: u8 = 0;
+const NOTIFIED: u8 = 1;
+const CANCELLED: u8 = 2;
+
+/// A coroutine suspended in the wait queue of a synchronization primitive.
+///
+/// Notifying a waiter hands it whatever it waits for (a lock, permits...): a waiter is either
+/// notified or cancelled, never both.
+pub(crate) struct Waiter {
+    task: Arc<Task>,
+    state: AtomicU8,
+    reason: ParkReason,
+    blocker: Option<Blocker>,
+}
+
+impl Waiter {
+    /// Returns a waiter for the current coroutine
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub(crate) fn new(reason: ParkReason) -> Arc<Self> {
+        Self::with_blocker(reason, None)
+    }
+
+    /// Returns a waiter for the current coroutine, reported as waiting on `blocker` when the
+    /// worker deadlocks
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub(crate) fn blocked_on(reason: ParkReason, blocker: Blocker) -> Arc<Self> {
+        Self::with_blocker(reason, Some(blocker))
+    }
+
+    fn with_blocker(reason: ParkReason, blocker: Option<Blocker>) -> Arc<Self> {
+        Arc::new(Self {
+            task: worker::current_task(),
+            state: AtomicU8::new(WAITING),
+            reason,
+            blocker,
+        })
+    }
+
+    /// Parks the task of the waiter, until `deadline` when given
+    fn park(&self, deadline: Option<Instant>) -> bool {
+        self.task.set_blocker(self.blocker);
+        let timed_out = match deadline {
+            Some(deadline) => worker::park_until(deadline, self.reason),
+            None => {
+                worker::park(self.reason);
+                false
+            }
+        };
+        self.task.set_blocker(None);
+        timed_out
+    }
+
+    #[inline(always)]
+    pub(crate) fn task_id(&self) -> CoroutineId {
+        self.task.id()
+    }
+
+    /// Returns `true` when the waiter was notified, `false` when it was cancelled
+    pub(crate) fn notify(&self) -> bool {
+        if self
+            .state
+            .compare_exchange(WAITING, NOTIFIED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.task.unpark();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` when the waiter was cancelled, `false` when it was already notified
+    pub(crate) fn cancel(&self) -> bool {
+        self.state
+            .compare_exchange(WAITING, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_notified(&self) -> bool {
+        self.state.load(Ordering::Acquire) == NOTIFIED
+    }
+
+    /// Suspends the current coroutine until the waiter is notified or `deadline` is reached,
+    /// returns `Ok(false)` when the waiter timed out (and is now cancelled).
+    ///
+    /// Fails when the coroutine is cancelled, or reaches the deadline of its `timeout` scope
+    /// first, before being notified, the waiter is then cancelled.
+    pub(crate) fn try_wait_deadline(&self, deadline: Option<Instant>) -> Result<bool, Interrupt> {
+        let scope = self
+            .task
+            .deadline()
+            .filter(|scope| deadline.is_none_or(|deadline| *scope < deadline));
+        loop {
+            if self.is_notified() {
+                return Ok(true);
+            }
+            if self.task.is_cancelled() {
+                return if self.cancel() {
+                    Err(Interrupt::Cancelled)
+                } else {
+                    Ok(true)
+                };
+            }
+            if self.park(scope.or(deadline)) {
+                return match (self.cancel(), scope) {
+                    (false, _) => Ok(true),
+                    (true, Some(_)) => Err(Interrupt::Expired),
+                    (true, None) => Ok(false),
+                };
+            }
+        }
+    }
+
+    /// Like [`try_wait_deadline`](Self::try_wait_deadline), unwinds the coroutine when it is
+    /// cancelled or reaches the deadline of its `timeout` scope
+    pub(crate) fn wait_deadline(&self, deadline: Option<Instant>) -> bool {
+        self.try_wait_deadline(deadline)
+            .unwrap_or_else(|interrupt| interrupt.unwind())
+    }
+
+    /// Suspends the current coroutine until the waiter is notified, unwinds the coroutine when it
+    /// is cancelled
+    pub(crate) fn wait(&self) {
+        self.wait_deadline(None);
+    }
+
+    /// Suspends the current coroutine until the waiter is notified, even when it is cancelled
+    pub(crate) fn wait_uncancellable(&self) {
+        while !self.is_notified() {
+            self.park(None);
+        }
+    }
+}
+
+/// A FIFO queue of waiters
+#[derive(Default)]
+pub(crate) struct WaitQueue {
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl WaitQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waiters: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, waiter: Arc<Waiter>) {
+        self.waiters.push_back(waiter);
+    }
+
+    /// Removes a waiter that gave up waiting, returns `false` when it already left the queue
+    pub(crate) fn remove(&mut self, waiter: &Arc<Waiter>) -> bool {
+        let len = self.waiters.len();
+        self.waiters.retain(|other| !Arc::ptr_eq(other, waiter));
+        self.waiters.len() != len
+    }
+
+    /// Notifies the first waiter still waiting, returns `false` when there is none
+    pub(crate) fn notify_one(&mut self) -> bool {
+        while let Some(waiter) = self.waiters.pop_front() {
+            if waiter.notify() {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub(crate) fn notify_all(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            waiter.notify();
+        }
+    }
+}
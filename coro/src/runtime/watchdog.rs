@@ -0,0 +1,368 @@
+use std::{
+    ffi::c_void,
+    fmt::Write,
+    mem,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{signal, task::Task, worker::Shared};
+
+/// Maximum number of frames of a captured backtrace
+const MAX_FRAMES: usize = 64;
+
+/// How long the watchdog waits for the stalled thread to capture its backtrace
+const CAPTURE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The real-time signal sent to a stalled worker so that it captures its own backtrace
+pub(crate) fn capture_signal() -> libc::c_int {
+    libc::SIGRTMIN() + 4
+}
+
+/// What a worker runs, updated at every swap while the watchdog is enabled
+pub(crate) struct Activity {
+    epoch: Instant,
+    /// When the running coroutine was swapped in, in nanoseconds since `epoch`, 0 while the
+    /// worker runs its own loop
+    since: AtomicU64,
+    task: Mutex<Option<Arc<Task>>>,
+    /// The usable stack of the running coroutine
+    stack_start: AtomicUsize,
+    stack_end: AtomicUsize,
+    /// The `pthread_t` of the worker thread
+    thread: AtomicU64,
+}
+
+impl Activity {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            since: AtomicU64::new(0),
+            task: Mutex::new(None),
+            stack_start: AtomicUsize::new(0),
+            stack_end: AtomicUsize::new(0),
+            thread: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the calling thread as the thread of the worker
+    pub(crate) fn set_thread(&self) {
+        self.thread
+            .store(unsafe { libc::pthread_self() } as u64, Ordering::Relaxed);
+    }
+
+    /// Records that the worker swaps into `task`, called from the thread of the worker
+    pub(crate) fn enter(&self, task: &Arc<Task>, now: Instant) {
+        *self.task.lock().unwrap() = Some(task.clone());
+        let stack = unsafe { task.context() }.map_or(0..0, |ctx| ctx.stack_range());
+        self.stack_start.store(stack.start, Ordering::Relaxed);
+        self.stack_end.store(stack.end, Ordering::Relaxed);
+        let since = now.duration_since(self.epoch).as_nanos() as u64;
+        self.since.store(since.max(1), Ordering::Release);
+    }
+
+    /// Records that the running coroutine swapped back to the worker
+    pub(crate) fn leave(&self) {
+        self.since.store(0, Ordering::Release);
+        self.task.lock().unwrap().take();
+    }
+
+    fn stack(&self) -> Range<usize> {
+        self.stack_start.load(Ordering::Relaxed)..self.stack_end.load(Ordering::Relaxed)
+    }
+}
+
+/// The instruction pointers captured by the signal handler, for one stalled thread at a time
+static FRAMES: [AtomicUsize; MAX_FRAMES] = [const { AtomicUsize::new(0) }; MAX_FRAMES];
+static FRAMES_LEN: AtomicUsize = AtomicUsize::new(0);
+/// The stack of the coroutine expected to run on the signaled thread
+static STACK_START: AtomicUsize = AtomicUsize::new(0);
+static STACK_END: AtomicUsize = AtomicUsize::new(0);
+/// Set by the watchdog before it sends the signal, taken by the handler
+static ARMED: AtomicBool = AtomicBool::new(false);
+static CAPTURED: AtomicBool = AtomicBool::new(false);
+/// Serializes the captures of every watchdog of the process
+static CAPTURE: Mutex<()> = Mutex::new(());
+
+/// Returns the instruction, stack and frame pointers of an interrupted thread
+///
+/// # Safety
+///  - `context` must be the `ucontext_t` passed to a signal handler
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+unsafe fn registers(context: *mut c_void) -> Option<(usize, usize, usize)> {
+    let gregs = unsafe { &(*(context as *const libc::ucontext_t)).uc_mcontext.gregs };
+    Some((
+        gregs[libc::REG_RIP as usize] as usize,
+        gregs[libc::REG_RSP as usize] as usize,
+        gregs[libc::REG_RBP as usize] as usize,
+    ))
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+unsafe fn registers(context: *mut c_void) -> Option<(usize, usize, usize)> {
+    let mcontext = unsafe { &(*(context as *const libc::ucontext_t)).uc_mcontext };
+    Some((
+        mcontext.pc as usize,
+        mcontext.sp as usize,
+        mcontext.regs[29] as usize,
+    ))
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+unsafe fn registers(_: *mut c_void) -> Option<(usize, usize, usize)> {
+    None
+}
+
+/// Captures the interrupted instruction pointer and the return addresses found by following
+/// the frame pointers
+///
+/// Only reads the stack of the interrupted coroutine, within its bounds, so that it stays
+/// async-signal-safe: unwinding would take locks of the dynamic loader. Code built without frame
+/// pointers cuts the backtrace short or adds bogus frames, see `-C force-frame-pointers`.
+extern "C" fn on_capture_signal(_: libc::c_int, _: *mut libc::siginfo_t, context: *mut c_void) {
+    if !ARMED.swap(false, Ordering::Acquire) {
+        return;
+    }
+    let mut len = 0;
+    if let Some((pc, sp, mut fp)) = unsafe { registers(context) } {
+        FRAMES[0].store(pc, Ordering::Relaxed);
+        len = 1;
+        let start = STACK_START.load(Ordering::Relaxed);
+        let end = STACK_END.load(Ordering::Relaxed);
+        // The coroutine may have swapped out since the watchdog read its stack
+        if (start..end).contains(&sp) {
+            // Each frame starts with the frame pointer of its caller then the return address
+            while len < MAX_FRAMES
+                && fp >= sp
+                && fp % mem::align_of::<usize>() == 0
+                && fp + 2 * mem::size_of::<usize>() <= end
+            {
+                let frame = fp as *const usize;
+                let (next, ret) = unsafe { (*frame, *frame.add(1)) };
+                if ret == 0 {
+                    break;
+                }
+                FRAMES[len].store(ret, Ordering::Relaxed);
+                len += 1;
+                if next <= fp {
+                    break;
+                }
+                fp = next;
+            }
+        }
+    }
+    FRAMES_LEN.store(len, Ordering::Relaxed);
+    CAPTURED.store(true, Ordering::Release);
+}
+
+fn install_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_capture_signal
+            as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut c_void)
+            as usize;
+        action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(capture_signal(), &action, std::ptr::null_mut());
+    });
+}
+
+/// Captures and symbolizes the backtrace of `thread` running on `stack`, `None` when it does not
+/// answer in time
+fn capture(thread: libc::pthread_t, stack: Range<usize>) -> Option<String> {
+    let _guard = CAPTURE.lock().unwrap();
+    STACK_START.store(stack.start, Ordering::Relaxed);
+    STACK_END.store(stack.end, Ordering::Relaxed);
+    CAPTURED.store(false, Ordering::Relaxed);
+    ARMED.store(true, Ordering::Release);
+    if unsafe { libc::pthread_kill(thread, capture_signal()) } != 0 {
+        ARMED.store(false, Ordering::Relaxed);
+        return None;
+    }
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    while !CAPTURED.load(Ordering::Acquire) {
+        if Instant::now() >= deadline && ARMED.swap(false, Ordering::Relaxed) {
+            // The handler never started, it now returns right away
+            return None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let mut out = String::new();
+    for (i, ip) in FRAMES[..FRAMES_LEN.load(Ordering::Relaxed)]
+        .iter()
+        .map(|ip| ip.load(Ordering::Relaxed))
+        .enumerate()
+    {
+        let mut resolved = false;
+        // A return address points after the call, which may belong to the next line
+        let call = if i == 0 { ip } else { ip - 1 };
+        backtrace::resolve(call as *mut c_void, |symbol| {
+            resolved = true;
+            let _ = write!(out, "{:4}: ", i);
+            match symbol.name() {
+                Some(name) => write!(out, "{:#}", name),
+                None => write!(out, "{:#x}", ip),
+            }
+            .unwrap();
+            if let (Some(file), Some(line)) = (symbol.filename(), symbol.lineno()) {
+                write!(out, "\n             at {}:{}", file.display(), line).unwrap();
+            }
+            out.push('\n');
+        });
+        if !resolved {
+            writeln!(out, "{:4}: {:#x}", i, ip).unwrap();
+        }
+    }
+    Some(out)
+}
+
+/// A thread reporting the coroutines running for longer than a threshold without yielding
+pub(crate) struct Watchdog {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn start(threshold: Duration, workers: Vec<Arc<Shared>>) -> Self {
+        install_handler();
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let their_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("coro-watchdog".to_string())
+            .spawn(move || {
+                signal::block_async_signals();
+                run(threshold, &workers, &their_stop);
+            })
+            .expect("Failed to spawn the coroutine watchdog");
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(threshold: Duration, workers: &[Arc<Shared>], stop: &(Mutex<bool>, Condvar)) {
+    let period = (threshold / 4).max(Duration::from_millis(1));
+    // The stall reported last for each worker, reported once
+    let mut reported = vec![0; workers.len()];
+    let mut stopped = stop.0.lock().unwrap();
+    while !*stopped {
+        stopped = stop.1.wait_timeout(stopped, period).unwrap().0;
+        for (worker, reported) in workers.iter().zip(&mut reported) {
+            let activity = worker.activity();
+            let since = activity.since.load(Ordering::Acquire);
+            if since == 0 || since == *reported {
+                continue;
+            }
+            let elapsed = activity
+                .epoch
+                .elapsed()
+                .saturating_sub(Duration::from_nanos(since));
+            if elapsed < threshold {
+                continue;
+            }
+            let Some(task) = activity.task.lock().unwrap().clone() else {
+                continue;
+            };
+            let backtrace = capture(
+                activity.thread.load(Ordering::Relaxed) as libc::pthread_t,
+                activity.stack(),
+            );
+            // The coroutine may have yielded while its thread was signaled
+            if activity.since.load(Ordering::Acquire) != since {
+                continue;
+            }
+            *reported = since;
+            log::warn!(
+                "Coroutine {} '{}' has been running for {:?} without yielding, backtrace:\n{}",
+                task.id(),
+                task.name().unwrap_or("unnamed"),
+                elapsed,
+                backtrace.as_deref().unwrap_or("<unavailable>\n")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::Builder, CoroutineBuilder};
+
+    #[inline(never)]
+    fn spin_until(stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            std::hint::spin_loop();
+        }
+    }
+
+    #[test]
+    fn test_capture_stalled_coroutine() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let their_stop = stop.clone();
+        let worker = thread::spawn(move || {
+            let runtime = Builder::new()
+                .watchdog(Duration::from_millis(10))
+                .build()
+                .unwrap();
+            tx.send(runtime.worker.shared().clone()).unwrap();
+            runtime.run(move || {
+                CoroutineBuilder::new()
+                    .name("spinner")
+                    .spawn(move || spin_until(&their_stop))
+                    .join()
+                    .unwrap();
+            });
+        });
+        let shared = rx.recv().unwrap();
+        let activity = shared.activity();
+        let task = loop {
+            let task = activity.task.lock().unwrap().clone();
+            match task {
+                Some(task) if task.name() == Some("spinner") => break task,
+                _ => thread::sleep(Duration::from_millis(1)),
+            }
+        };
+        let since = activity.since.load(Ordering::Acquire);
+        // The worker may still be swapping into the coroutine
+        let mut backtrace = String::new();
+        for _ in 0..100 {
+            backtrace = capture(
+                activity.thread.load(Ordering::Relaxed) as libc::pthread_t,
+                activity.stack(),
+            )
+            .unwrap();
+            if backtrace.contains("spin_until") {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        stop.store(true, Ordering::Relaxed);
+        worker.join().unwrap();
+
+        assert_eq!(task.name(), Some("spinner"));
+        assert_ne!(since, 0);
+        assert!(backtrace.contains("spin_until"), "{}", backtrace);
+    }
+}
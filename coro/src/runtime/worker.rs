@@ -0,0 +1,635 @@
+use std::{
+    any::Any,
+    cell::{Cell, RefCell, UnsafeCell},
+    io, mem, panic,
+    sync::{
+        atomic::{self, AtomicBool, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+use ucontext::UContext;
+
+use super::{
+    blocking::BlockingPool,
+    cancel::{self, Cancelled},
+    deadlock::{Deadlock, DeadlockHook},
+    info::{CoroutineId, CoroutineInfo, ParkReason},
+    metrics::{Counters, MetricsHandle},
+    queue::RunQueue,
+    reactor::{Reactor, Waker},
+    signal::Signals,
+    task::{CoroutineBuilder, JoinHandle, Packet, Task},
+    timer::Timers,
+    uring::Uring,
+    watchdog::Activity,
+};
+
+thread_local! {
+    static CURRENT: Cell<*const Worker> = const { Cell::new(std::ptr::null()) };
+}
+
+/// The part of a worker reachable from other threads
+pub(crate) struct Shared {
+    /// The position of the worker in its runtime
+    index: usize,
+    run_queue: Mutex<RunQueue>,
+    tasks: Mutex<FxHashMap<CoroutineId, Arc<Task>>>,
+    waker: Arc<Waker>,
+    metrics: Counters,
+    activity: Activity,
+    /// Set when the running coroutine exhausted its time slice
+    preempt: AtomicBool,
+    /// Set while the worker sleeps without runnable coroutines, until it is woken up to steal
+    idle: AtomicBool,
+    /// The other workers of the runtime, on the same NUMA node first
+    peers: OnceLock<Box<[Weak<Shared>]>>,
+}
+
+impl Shared {
+    /// Pushes a runnable task to the run queue of this worker
+    pub(crate) fn schedule(&self, task: Arc<Task>) {
+        let movable = task.is_movable();
+        self.run_queue.lock().unwrap().push(task);
+        if !current().is_some_and(|worker| std::ptr::eq(Arc::as_ptr(&worker.shared), self)) {
+            self.metrics.on_remote_wakeup();
+            self.waker.wake();
+        }
+        if movable {
+            self.wake_idle_peer();
+        }
+    }
+
+    /// Sets the other workers of the runtime, ordered by preference to steal from them
+    pub(crate) fn set_peers(&self, peers: Vec<Weak<Shared>>) {
+        let _ = self.peers.set(peers.into_boxed_slice());
+    }
+
+    fn peers(&self) -> impl Iterator<Item = Arc<Shared>> + '_ {
+        self.peers
+            .get()
+            .into_iter()
+            .flatten()
+            .filter_map(Weak::upgrade)
+    }
+
+    /// Wakes up an idle peer to steal the movable coroutines of this worker
+    fn wake_idle_peer(&self) {
+        if self.peers.get().is_none_or(|peers| peers.is_empty()) {
+            return;
+        }
+        // Pairs with the fence of an idle peer: either it sees the pushed task or it is woken up
+        atomic::fence(Ordering::SeqCst);
+        for peer in self.peers() {
+            if peer.idle.load(Ordering::Relaxed) && peer.idle.swap(false, Ordering::SeqCst) {
+                peer.waker.wake();
+                return;
+            }
+        }
+    }
+
+    /// Interrupts the worker if it sleeps
+    #[inline(always)]
+    pub(crate) fn wake(&self) {
+        self.waker.wake();
+    }
+
+    #[inline(always)]
+    pub(crate) fn metrics(&self) -> &Counters {
+        &self.metrics
+    }
+
+    /// Asks the running coroutine to yield at its next preemption point
+    #[inline(always)]
+    pub(crate) fn request_yield(&self) {
+        self.preempt.store(true, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub(crate) fn activity(&self) -> &Activity {
+        &self.activity
+    }
+}
+
+/// A coroutine scheduler running on a single thread.
+///
+/// The worker loop runs on the root context of its thread and swaps into runnable coroutines,
+/// which swap back to it when they park, yield or return. Once out of coroutines, the worker
+/// steals the movable ones of its peers.
+pub(crate) struct Worker {
+    root: UnsafeCell<UContext>,
+    current: RefCell<Option<Arc<Task>>>,
+    shared: Arc<Shared>,
+    reactor: Reactor,
+    /// `None` when the kernel does not support io_uring, the files use the blocking pool then
+    uring: Option<Uring>,
+    timers: Timers,
+    signals: Signals,
+    blocking: Arc<BlockingPool>,
+    stack_size: usize,
+    /// The activity is only recorded when the watchdog is enabled, which reports the stalls
+    watchdog: bool,
+    on_deadlock: Option<DeadlockHook>,
+    /// Set once the current deadlock was reported, until a coroutine runs again
+    deadlock_reported: Cell<bool>,
+}
+
+/// Returns the worker running on the current thread
+///
+/// The worker is only installed while `Runtime::run` executes and every coroutine is stopped
+/// before it returns, so the reference never outlives the worker.
+#[inline(always)]
+pub(crate) fn current() -> Option<&'static Worker> {
+    let worker = CURRENT.get();
+    if worker.is_null() {
+        None
+    } else {
+        Some(unsafe { &*worker })
+    }
+}
+
+/// Returns the task of the running coroutine
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub(crate) fn current_task() -> Arc<Task> {
+    current()
+        .and_then(|worker| worker.current.borrow().clone())
+        .expect("Not running inside a coroutine")
+}
+
+/// Suspends the current coroutine until its task is unparked
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub(crate) fn park(reason: ParkReason) {
+    let worker = current().expect("Not running inside a coroutine runtime");
+    let task = current_task();
+    task.set_park_reason(Some(reason));
+    if task.try_park() {
+        worker.switch_to_root(&task);
+    }
+    task.set_park_reason(None);
+}
+
+/// Suspends the current coroutine until its task is unparked or `deadline` is reached, returns
+/// `true` when the deadline is reached
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub(crate) fn park_until(deadline: Instant, reason: ParkReason) -> bool {
+    let worker = current().expect("Not running inside a coroutine runtime");
+    if Instant::now() >= deadline {
+        return true;
+    }
+    let task = current_task();
+    // The timer is removed from the timers of this worker
+    let _pin = task.pin();
+    let timer = worker.timers.insert(deadline, task.clone());
+    task.set_park_reason(Some(reason));
+    if task.try_park() {
+        worker.switch_to_root(&task);
+    }
+    task.set_park_reason(None);
+    worker.timers.remove(timer);
+    Instant::now() >= deadline
+}
+
+/// Suspends the current coroutine and pushes it at the back of the run queue, unwinds it when
+/// it was cancelled
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub(crate) fn yield_now() {
+    let worker = current().expect("Not running inside a coroutine runtime");
+    let task = current_task();
+    if task.is_cancelled() {
+        cancel::unwind();
+    }
+    task.set_scheduled();
+    worker
+        .shared
+        .run_queue
+        .lock()
+        .unwrap()
+        .push_yielded(task.clone());
+    worker.switch_to_root(&task);
+}
+
+/// Returns `true` when the running coroutine exhausted its time slice
+#[inline(always)]
+pub(crate) fn should_yield() -> bool {
+    current().is_some_and(|worker| {
+        worker.shared.preempt.load(Ordering::Relaxed) && worker.current.borrow().is_some()
+    })
+}
+
+/// Yields when the running coroutine exhausted its time slice, a cancelled coroutine is not
+/// unwound: the runtime calls report it on their own
+#[inline(always)]
+pub(crate) fn preemption_point() {
+    if should_yield() {
+        let worker = current().unwrap();
+        let task = current_task();
+        task.set_scheduled();
+        worker
+            .shared
+            .run_queue
+            .lock()
+            .unwrap()
+            .push_yielded(task.clone());
+        worker.switch_to_root(&task);
+    }
+}
+
+/// Restores the previously installed worker on drop
+pub(crate) struct EnterGuard(*const Worker);
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.set(self.0);
+    }
+}
+
+impl Worker {
+    pub(crate) fn new(
+        index: usize,
+        stack_size: usize,
+        blocking: Arc<BlockingPool>,
+        watchdog: bool,
+        on_deadlock: Option<DeadlockHook>,
+    ) -> io::Result<Self> {
+        let root = UContext::get().ok_or_else(|| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        let reactor = Reactor::new()?;
+        let waker = reactor.waker();
+        let uring = Uring::new(waker.as_raw_fd())
+            .inspect_err(|err| log::debug!("Files use the blocking pool, no io_uring: {}", err))
+            .ok();
+        Ok(Self {
+            root: UnsafeCell::new(root),
+            current: RefCell::new(None),
+            shared: Arc::new(Shared {
+                index,
+                run_queue: Mutex::new(RunQueue::default()),
+                tasks: Mutex::new(FxHashMap::default()),
+                waker,
+                metrics: Counters::default(),
+                activity: Activity::new(),
+                preempt: AtomicBool::new(false),
+                idle: AtomicBool::new(false),
+                peers: OnceLock::new(),
+            }),
+            reactor,
+            uring,
+            timers: Timers::default(),
+            signals: Signals::new(),
+            blocking,
+            stack_size,
+            watchdog,
+            on_deadlock,
+            deadlock_reported: Cell::new(false),
+        })
+    }
+
+    /// Installs this worker as the worker of the current thread
+    pub(crate) fn enter(&self) -> EnterGuard {
+        EnterGuard(CURRENT.replace(self as _))
+    }
+
+    #[inline(always)]
+    pub(crate) fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+
+    #[inline(always)]
+    pub(crate) fn reactor(&self) -> &Reactor {
+        &self.reactor
+    }
+
+    #[inline(always)]
+    pub(crate) fn uring(&self) -> Option<&Uring> {
+        self.uring.as_ref()
+    }
+
+    #[inline(always)]
+    pub(crate) fn signals(&self) -> &Signals {
+        &self.signals
+    }
+
+    #[inline(always)]
+    pub(crate) fn blocking(&self) -> &BlockingPool {
+        &self.blocking
+    }
+
+    /// Returns a handle to the metrics of every worker of the runtime
+    pub(crate) fn metrics_handle(&self) -> MetricsHandle {
+        let mut workers: Vec<_> = self.shared.peers().collect();
+        workers.push(self.shared.clone());
+        workers.sort_by_key(|worker| worker.index);
+        MetricsHandle::new(workers)
+    }
+
+    pub(crate) fn spawn<F, T>(&self, builder: CoroutineBuilder, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        unsafe { self.spawn_unchecked(builder, f) }
+    }
+
+    /// Returns the live task `id`
+    pub(crate) fn task(&self, id: CoroutineId) -> Option<Arc<Task>> {
+        self.shared.tasks.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Returns a snapshot of every live task
+    pub(crate) fn dump(&self) -> Vec<CoroutineInfo> {
+        let mut infos: Vec<_> = self
+            .shared
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|task| task.info())
+            .collect();
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
+    /// Like [`spawn`](Self::spawn) but the coroutine may borrow from its caller
+    ///
+    /// # Safety
+    ///  - The coroutine **MUST** finish before anything borrowed by `f` or `T` is dropped
+    pub(crate) unsafe fn spawn_unchecked<'a, F, T>(
+        &self,
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + 'a,
+        T: 'a,
+    {
+        let packet = Packet::new();
+        let main: Box<dyn FnOnce() + 'a> = Box::new(Self::body(packet.clone(), f));
+        // Safety: guaranteed by the caller
+        let main: Box<dyn FnOnce() + 'static> = unsafe { std::mem::transmute(main) };
+        let ctx = UContext::pinned(main, self.stack_size);
+        self.spawn_context(builder, ctx, packet)
+    }
+
+    /// Like [`spawn`](Self::spawn) but the coroutine may be stolen by the other workers, see
+    /// [`spawn_movable`](crate::spawn_movable)
+    pub(crate) fn spawn_movable<F, T>(&self, builder: CoroutineBuilder, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let packet = Packet::new();
+        let ctx = UContext::movable(Self::body(packet.clone(), f), self.stack_size);
+        self.spawn_context(builder, ctx, packet)
+    }
+
+    /// Returns the function of a coroutine running `f`
+    fn body<F, T>(packet: Arc<Packet<T>>, f: F) -> impl FnOnce()
+    where
+        F: FnOnce() -> T,
+    {
+        move || {
+            let task = current_task();
+            let result = if task.is_cancelled() {
+                // Cancelled before it started, `f` never runs
+                Err(Box::new(Cancelled) as Box<dyn Any + Send>)
+            } else {
+                panic::catch_unwind(panic::AssertUnwindSafe(f))
+            };
+            packet.complete(result);
+            task.set_done();
+        }
+    }
+
+    fn spawn_context<T>(
+        &self,
+        builder: CoroutineBuilder,
+        ctx: Option<UContext>,
+        packet: Arc<Packet<T>>,
+    ) -> JoinHandle<T> {
+        let mut ctx = ctx.expect("Failed to allocate a coroutine");
+        assert!(ctx.init(), "Failed to allocate a coroutine stack");
+        ctx.set_exit_context(Some(unsafe { &*self.root.get() }));
+
+        let task = Task::new(ctx, builder, self.shared.clone());
+        packet.set_owner(task.id());
+        log::trace!(
+            "Coroutine {} spawned ({})",
+            task.id(),
+            task.name().unwrap_or("unnamed")
+        );
+        self.shared.metrics.on_spawn();
+        self.shared
+            .tasks
+            .lock()
+            .unwrap()
+            .insert(task.id(), task.clone());
+        self.shared.schedule(task.clone());
+        JoinHandle::new(packet, task)
+    }
+
+    /// Runs `f` as the main coroutine, until it returns
+    pub(crate) fn run<F, T>(&self, f: F) -> thread::Result<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        self.shared.activity.set_thread();
+        let main = self.spawn(CoroutineBuilder::new().name("main"), f);
+        self.run_until(|| main.is_finished());
+        self.shutdown();
+        main.packet()
+            .take()
+            .expect("The main coroutine finished without a result")
+    }
+
+    /// Runs the coroutines of a helper worker until `running` is cleared
+    pub(crate) fn run_helper(&self, running: &AtomicBool) {
+        self.shared.activity.set_thread();
+        self.run_until(|| !running.load(Ordering::Acquire));
+        self.shutdown();
+    }
+
+    fn run_until(&self, done: impl Fn() -> bool) {
+        loop {
+            self.tick();
+            if done() {
+                break;
+            }
+            let mut timeout = if self.shared.run_queue.lock().unwrap().is_empty() {
+                self.timers.next_timeout(Instant::now())
+            } else {
+                Some(Duration::ZERO)
+            };
+            if timeout != Some(Duration::ZERO) && self.steal() {
+                timeout = Some(Duration::ZERO);
+            }
+            if timeout.is_none() {
+                self.check_deadlock();
+            }
+            let events = self
+                .reactor
+                .poll(timeout)
+                .expect("Failed to poll the coroutine reactor");
+            self.shared.idle.store(false, Ordering::Relaxed);
+            self.shared.metrics.on_reactor_poll(events);
+            if let Some(uring) = &self.uring {
+                uring.complete();
+            }
+            self.timers.fire(Instant::now());
+        }
+    }
+
+    /// Moves up to half of the stealable coroutines of the first peer having some to this
+    /// worker, returns `false` when there was none. The worker is marked idle until it polls
+    /// again otherwise.
+    fn steal(&self) -> bool {
+        if self.shared.peers.get().is_none_or(|peers| peers.is_empty()) {
+            return false;
+        }
+        // A peer pushing a movable coroutine from now on wakes this worker up
+        self.shared.idle.store(true, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        for victim in self.shared.peers() {
+            let stolen: Vec<_> = {
+                let mut run_queue = victim.run_queue.lock().unwrap();
+                let stolen = run_queue.steal(Task::is_stealable);
+                if stolen.is_empty() {
+                    continue;
+                }
+                // Removed under the run queue lock: a task missing from the map is released by
+                // the shutdown of the victim
+                let mut tasks = victim.tasks.lock().unwrap();
+                stolen
+                    .into_iter()
+                    .filter(|task| tasks.remove(&task.id()).is_some())
+                    .collect()
+            };
+            if stolen.is_empty() {
+                continue;
+            }
+            self.shared.idle.store(false, Ordering::Relaxed);
+            log::trace!(
+                "Worker {} stole {} coroutines from worker {}",
+                self.shared.index,
+                stolen.len(),
+                victim.index
+            );
+            {
+                let root = unsafe { &*self.root.get() };
+                let mut tasks = self.shared.tasks.lock().unwrap();
+                for task in &stolen {
+                    unsafe { task.migrate(root, self.shared.clone()) };
+                    tasks.insert(task.id(), task.clone());
+                }
+            }
+            let mut run_queue = self.shared.run_queue.lock().unwrap();
+            for task in stolen.into_iter().rev() {
+                run_queue.push(task);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Reports the deadlock of the coroutines, once, when the worker is about to sleep without a
+    /// timeout
+    fn check_deadlock(&self) {
+        let Some(hook) = &self.on_deadlock else {
+            return;
+        };
+        if self.deadlock_reported.get() || !self.shared.run_queue.lock().unwrap().is_empty() {
+            return;
+        }
+        let Some(deadlock) = Deadlock::detect(self.shared.tasks.lock().unwrap().values()) else {
+            return;
+        };
+        self.deadlock_reported.set(true);
+        log::error!("{}", deadlock);
+        if let Err(payload) = panic::catch_unwind(panic::AssertUnwindSafe(|| hook(&deadlock))) {
+            self.shutdown();
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// Resumes every coroutine that was runnable at the start of the tick
+    fn tick(&self) {
+        let n = self.shared.run_queue.lock().unwrap().len();
+        self.shared.metrics.set_run_queue_depth(n);
+        if n > 0 {
+            self.deadlock_reported.set(false);
+        }
+        for _ in 0..n {
+            let task = self.shared.run_queue.lock().unwrap().pop();
+            match task {
+                Some(task) => self.run_task(task),
+                None => break,
+            }
+        }
+    }
+
+    fn run_task(&self, task: Arc<Task>) {
+        if task.is_done() {
+            return;
+        }
+        task.set_running();
+        *self.current.borrow_mut() = Some(task.clone());
+        let start = Instant::now();
+        self.shared.preempt.store(false, Ordering::Relaxed);
+        if self.watchdog {
+            self.shared.activity.enter(&task, start);
+        }
+        unsafe {
+            // Events of the coroutine are attributed to its span until it swaps out
+            #[cfg(feature = "tracing")]
+            let _entered = task.span().enter();
+            let ctx = task.context().expect("Scheduled a released coroutine");
+            task.set_resumed(true);
+            (*self.root.get()).swap(ctx);
+            task.set_resumed(false);
+        }
+        self.current.borrow_mut().take();
+        let done = task.is_done();
+        let elapsed = start.elapsed();
+        self.shared.metrics.on_run(elapsed, done);
+        if self.watchdog {
+            self.shared.activity.leave();
+        }
+        if done {
+            log::trace!("Coroutine {} finished", task.id());
+            self.shared.tasks.lock().unwrap().remove(&task.id());
+            unsafe { task.release() };
+        }
+    }
+
+    #[inline(always)]
+    fn switch_to_root(&self, task: &Task) {
+        unsafe {
+            let ctx = task.context().expect("Running a released coroutine");
+            ctx.swap(&mut *self.root.get());
+        }
+    }
+
+    /// Releases every remaining coroutine, the values living on their stacks are leaked
+    fn shutdown(&self) {
+        self.shared.run_queue.lock().unwrap().clear();
+        self.timers.clear();
+        let mut tasks: Vec<_> = mem::take(&mut *self.shared.tasks.lock().unwrap())
+            .into_values()
+            .collect();
+        // A scoped coroutine which never started still owns its closure, which may borrow from
+        // the stacks of the coroutines spawned before it: release the youngest ones first
+        tasks.sort_unstable_by_key(|task| std::cmp::Reverse(task.id()));
+        for task in tasks {
+            unsafe { task.release() };
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Structured concurrency: coroutines which cannot outlive their parent
+
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    fmt,
+    marker::PhantomData,
+    panic,
+    rc::Rc,
+    sync::Arc,
+    thread,
+};
+
+use crate::runtime::{
+    self, CancelToken, Cancelled, CoroutineBuilder, Interrupt, JoinHandle, Packet, ParkReason,
+};
+
+/// A child coroutine, as seen by its scope
+trait Child {
+    fn try_wait_finished(&self) -> Result<(), Interrupt>;
+    fn wait_finished_uncancellable(&self);
+    fn take_panic(&self) -> Option<Box<dyn Any + Send>>;
+    fn cancel(&self);
+}
+
+struct ChildPacket<T> {
+    packet: Arc<Packet<T>>,
+    token: CancelToken,
+}
+
+impl<T> Child for ChildPacket<T> {
+    fn try_wait_finished(&self) -> Result<(), Interrupt> {
+        self.packet.try_wait_finished(ParkReason::Join)
+    }
+
+    fn wait_finished_uncancellable(&self) {
+        self.packet.wait_finished_uncancellable(ParkReason::Join)
+    }
+
+    fn take_panic(&self) -> Option<Box<dyn Any + Send>> {
+        self.packet.take_panic()
+    }
+
+    fn cancel(&self) {
+        self.token.cancel();
+    }
+}
+
+struct ScopeData {
+    /// The children borrow from the scope, their lifetime is erased as they are all joined
+    /// before the scope returns
+    children: RefCell<Vec<Rc<dyn Child>>>,
+    /// Indices of the children that panicked, in order
+    panicked: RefCell<Vec<usize>>,
+    cancelled: Cell<bool>,
+}
+
+impl ScopeData {
+    /// Suspends the current coroutine until every child finished, including the children spawned
+    /// in the meantime
+    ///
+    /// The children borrow from the current coroutine: when it is cancelled or reaches the
+    /// deadline of its `timeout` scope, they are cancelled too and still waited for. Returns
+    /// the last interruption.
+    fn join_all(&self) -> Option<Interrupt> {
+        let mut interrupted = None;
+        let mut i = 0;
+        loop {
+            let child = match self.children.borrow().get(i) {
+                Some(child) => child.clone(),
+                None => return interrupted,
+            };
+            if let Err(interrupt) = child.try_wait_finished() {
+                self.cancel();
+                child.wait_finished_uncancellable();
+                interrupted = Some(interrupt);
+            }
+            i += 1;
+        }
+    }
+
+    /// Cancels every child
+    fn cancel(&self) {
+        self.cancelled.set(true);
+        let children = self.children.borrow().clone();
+        for child in children {
+            child.cancel();
+        }
+    }
+
+    /// Takes the panic payload of the first child that panicked, unless it was joined
+    fn take_first_panic(&self) -> Option<Box<dyn Any + Send>> {
+        let children = self.children.borrow();
+        self.panicked
+            .borrow()
+            .iter()
+            .find_map(|&i| children[i].take_panic())
+    }
+}
+
+/// A scope to spawn coroutines borrowing from the enclosing coroutine, see [`scope`]
+///
+/// The scoped coroutines run on the worker of the enclosing coroutine.
+pub struct Scope<'scope, 'env: 'scope> {
+    data: Rc<ScopeData>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// An owned permission to join on a scoped coroutine
+pub struct ScopedJoinHandle<'scope, T> {
+    handle: JoinHandle<T>,
+    scope: PhantomData<&'scope ()>,
+}
+
+/// Runs `f` with a [`Scope`] to spawn coroutines, and suspends the current coroutine until all
+/// of them finished.
+///
+/// Unlike [`spawn`](crate::spawn), the scoped coroutines may borrow non-`'static` data from
+/// the enclosing coroutine as the scope joins them before returning.
+///
+/// When a scoped coroutine panics, the other ones are cancelled and the panic is propagated
+/// once all of them finished, unless it was handled by joining the coroutine. Cancelling the
+/// enclosing coroutine cancels the scoped ones too.
+///
+/// # Panics
+///  - When called outside of a coroutine
+///  - When `f` or a scoped coroutine panics
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    // The scoped coroutines share the data of the scope with the enclosing one
+    let task = runtime::current_task();
+    let _pin = task.pin();
+    let scope = Scope {
+        data: Rc::new(ScopeData {
+            children: RefCell::new(Vec::new()),
+            panicked: RefCell::new(Vec::new()),
+            cancelled: Cell::new(false),
+        }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f(&scope)));
+    if result.is_err() {
+        scope.data.cancel();
+    }
+    let interrupted = scope.data.join_all();
+    match result {
+        Err(payload) => panic::resume_unwind(payload),
+        Ok(output) => match scope.data.take_first_panic() {
+            Some(payload) => panic::resume_unwind(payload),
+            // The deadline of the enclosing `timeout` is only reached once the children finished
+            None if interrupted == Some(Interrupt::Expired) => Interrupt::Expired.unwind(),
+            None => output,
+        },
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Spawns a coroutine joined at the end of the scope
+    ///
+    /// # Panics
+    ///  - When called outside of a runtime
+    ///  - When the coroutine stack cannot be allocated
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        self.spawn_with(CoroutineBuilder::new(), f)
+    }
+
+    pub(crate) fn spawn_with<F, T>(
+        &'scope self,
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        let worker =
+            runtime::current_worker().expect("`spawn` called outside of a coroutine runtime");
+        let mut children = self.data.children.borrow_mut();
+        let index = children.len();
+        let data = self.data.clone();
+        let f = move || match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+            Ok(output) => output,
+            Err(payload) => {
+                // Being cancelled is not a failure of the child
+                if !payload.is::<Cancelled>() {
+                    data.panicked.borrow_mut().push(index);
+                    data.cancel();
+                }
+                panic::resume_unwind(payload)
+            }
+        };
+        // Safety: `scope` waits for every child before returning, which outlives 'scope. When the
+        // runtime shuts down with the scope still waiting, the worker releases the child before
+        // the coroutines it borrows from, which were spawned before it
+        let handle = unsafe { worker.spawn_unchecked(builder, f) };
+        let child: Rc<dyn Child + 'scope> = Rc::new(ChildPacket {
+            packet: handle.packet().clone(),
+            token: handle.cancel_token(),
+        });
+        children
+            .push(unsafe { std::mem::transmute::<Rc<dyn Child + 'scope>, Rc<dyn Child>>(child) });
+        ScopedJoinHandle {
+            handle,
+            scope: PhantomData,
+        }
+    }
+
+    /// Cancels every coroutine of the scope, see [`Cancelled`]
+    pub fn cancel(&self) {
+        self.data.cancel();
+    }
+
+    /// Returns `true` once the scope was cancelled or a scoped coroutine panicked
+    pub fn is_cancelled(&self) -> bool {
+        self.data.cancelled.get()
+    }
+}
+
+impl fmt::Debug for Scope<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("children", &self.data.children.borrow().len())
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Suspends the current coroutine until the scoped coroutine returns.
+    ///
+    /// Returns `Err` with the panic payload when the coroutine panicked, the panic is then not
+    /// propagated by the scope.
+    pub fn join(self) -> thread::Result<T> {
+        self.handle.join()
+    }
+
+    /// Returns `true` when the coroutine returned (or panicked)
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl<T> fmt::Debug for ScopedJoinHandle<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedJoinHandle").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::yield_now;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_scope_borrow() {
+        crate::run(|| {
+            let mut values = vec![1, 2, 3];
+            let total = Cell::new(0);
+            scope(|s| {
+                for value in &values {
+                    let total = &total;
+                    s.spawn(move || {
+                        yield_now();
+                        total.set(total.get() + value);
+                    });
+                }
+                // Nested spawns are joined too
+                s.spawn(|| {
+                    s.spawn(|| total.set(total.get() + 10));
+                });
+            });
+            assert_eq!(total.get(), 16);
+            values.push(4);
+        });
+    }
+
+    #[test]
+    fn test_scope_panic() {
+        crate::run(|| {
+            let result = panic::catch_unwind(|| {
+                scope(|s| {
+                    s.spawn(|| panic!("first"));
+                    s.spawn(|| {
+                        while !s.is_cancelled() {
+                            yield_now();
+                        }
+                    });
+                })
+            });
+            let payload = result.unwrap_err();
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"first"));
+
+            // A joined panic is handled
+            let value = scope(|s| {
+                let handle = s.spawn(|| panic!("joined"));
+                handle.join().unwrap_err();
+                7
+            });
+            assert_eq!(value, 7);
+        });
+    }
+    #[test]
+    fn test_scope_shutdown() {
+        use std::sync::{
+            atomic::{AtomicBool, AtomicU32, Ordering},
+            Arc,
+        };
+
+        /// Set when the context of the scope owner is released
+        struct Released(Arc<AtomicBool>);
+
+        impl Drop for Released {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        crate::coroutine_local! {
+            static OWNER: RefCell<Option<Released>> = RefCell::new(None);
+        }
+
+        /// Reads the stack of the scope owner when dropped
+        struct Probe<'a>(&'a u32, Arc<AtomicBool>, Arc<AtomicU32>);
+
+        impl Drop for Probe<'_> {
+            fn drop(&mut self) {
+                assert!(!self.1.load(Ordering::SeqCst), "Scope owner released first");
+                self.2.store(*self.0, Ordering::SeqCst);
+            }
+        }
+
+        let released = Arc::new(AtomicBool::new(false));
+        let read = Arc::new(AtomicU32::new(0));
+        let (their_released, their_read) = (released.clone(), read.clone());
+        crate::run(move || {
+            crate::spawn(move || {
+                OWNER.with(|owner| *owner.borrow_mut() = Some(Released(their_released.clone())));
+                let value = 42;
+                scope(|s| {
+                    // Parked when `main` returns
+                    s.spawn(|| crate::sleep(std::time::Duration::from_secs(3600)));
+                    // Never started
+                    let probe = Probe(&value, their_released, their_read);
+                    s.spawn(move || drop(probe));
+                });
+            });
+            yield_now();
+        });
+        assert!(released.load(Ordering::SeqCst));
+        assert_eq!(read.load(Ordering::SeqCst), 42);
+    }
+}
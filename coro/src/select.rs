@@ -0,0 +1,445 @@
+//! Waiting on several events at once.
+//!
+//! A [`Select`] registers the current coroutine with every [`Source`] at once, resumes it on
+//! the first ready one and deregisters it from the others. The [`select!`](crate::select!)
+//! macro is the usual way to build one:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use coro::sync::mpsc;
+//!
+//! coro::run(|| {
+//!     let (tx, rx) = mpsc::channel::<u32>();
+//!     let (_tx2, rx2) = mpsc::channel::<String>();
+//!     coro::spawn(move || tx.send(1).unwrap());
+//!     coro::select! {
+//!         n = &rx => println!("number: {n:?}"),
+//!         s = &rx2 => println!("string: {s:?}"),
+//!         timeout(Duration::from_secs(1)) => println!("timed out"),
+//!     }
+//! });
+//! ```
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::runtime::{ParkReason, Waiter};
+
+pub use crate::io::Ready;
+
+/// The registration of a coroutine waiting in a [`Select`]
+pub struct Token {
+    waiter: Arc<Waiter>,
+}
+
+impl Token {
+    #[inline(always)]
+    pub(crate) fn waiter(&self) -> &Arc<Waiter> {
+        &self.waiter
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token").finish_non_exhaustive()
+    }
+}
+
+/// An event a [`Select`] can wait for
+pub trait Source {
+    type Output;
+
+    /// Completes the event if it is ready, never suspends
+    fn try_complete(&mut self) -> Option<Self::Output>;
+
+    /// Notifies `token` once the event may be ready
+    fn register(&mut self, token: &Token);
+
+    /// Stops notifying `token`, passes a notification it did not consume on to the next waiter
+    fn unregister(&mut self, token: &Token);
+
+    /// Returns `false` when only the coroutines of the runtime can make the event ready, `true`
+    /// by default. A select on such events only counts as blocked for the deadlock detection,
+    /// see [`Builder::detect_deadlocks`](crate::runtime::Builder::detect_deadlocks).
+    fn is_external(&self) -> bool {
+        true
+    }
+}
+
+trait Branch {
+    fn try_complete(&mut self) -> bool;
+    fn register(&mut self, token: &Token);
+    fn unregister(&mut self, token: &Token);
+    fn is_external(&self) -> bool;
+}
+
+struct On<S, F> {
+    source: S,
+    f: Option<F>,
+}
+
+impl<S: Source, F: FnOnce(S::Output)> Branch for On<S, F> {
+    fn try_complete(&mut self) -> bool {
+        match self.source.try_complete() {
+            Some(output) => {
+                (self.f.take().unwrap())(output);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn register(&mut self, token: &Token) {
+        self.source.register(token);
+    }
+
+    fn unregister(&mut self, token: &Token) {
+        self.source.unregister(token);
+    }
+
+    fn is_external(&self) -> bool {
+        self.source.is_external()
+    }
+}
+
+/// Waits for the first of several events
+///
+/// The branches are polled in the order they were added: when several events are ready, the
+/// first one wins.
+#[must_use]
+pub struct Select<'a> {
+    branches: Vec<Box<dyn Branch + 'a>>,
+    timeout: Option<(Instant, Box<dyn FnOnce() + 'a>)>,
+    default: Option<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+            timeout: None,
+            default: None,
+        }
+    }
+
+    /// Adds a branch calling `f` with the output of `source` once it is ready
+    pub fn on<S, F>(mut self, source: S, f: F) -> Self
+    where
+        S: Source + 'a,
+        F: FnOnce(S::Output) + 'a,
+    {
+        self.branches.push(Box::new(On { source, f: Some(f) }));
+        self
+    }
+
+    /// Calls `f` when no event is ready after `timeout`
+    pub fn timeout<F: FnOnce() + 'a>(self, timeout: Duration, f: F) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.deadline(deadline, f),
+            None => self,
+        }
+    }
+
+    /// Calls `f` when no event is ready at `deadline`, the earliest deadline applies
+    pub fn deadline<F: FnOnce() + 'a>(mut self, deadline: Instant, f: F) -> Self {
+        if self
+            .timeout
+            .as_ref()
+            .is_none_or(|(other, _)| deadline < *other)
+        {
+            self.timeout = Some((deadline, Box::new(f)));
+        }
+        self
+    }
+
+    /// Calls `f` when no event is ready right away, the select never suspends
+    pub fn default<F: FnOnce() + 'a>(mut self, f: F) -> Self {
+        self.default = Some(Box::new(f));
+        self
+    }
+
+    /// Suspends the current coroutine until one event is ready and runs its branch
+    ///
+    /// # Panics
+    ///  - When no event is ready and this is called outside of a coroutine
+    ///  - When there is nothing to wait for
+    pub fn wait(mut self) {
+        assert!(
+            !self.branches.is_empty() || self.timeout.is_some() || self.default.is_some(),
+            "Nothing to select"
+        );
+        loop {
+            if self.try_complete() {
+                return;
+            }
+            if let Some(default) = self.default.take() {
+                return default();
+            }
+            let deadline = self.timeout.as_ref().map(|(deadline, _)| *deadline);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return (self.timeout.take().unwrap().1)();
+            }
+
+            let reason = if self.branches.iter().any(|branch| branch.is_external()) {
+                ParkReason::SelectExternal
+            } else {
+                ParkReason::Select
+            };
+            let token = Token {
+                waiter: Waiter::new(reason),
+            };
+            for branch in self.branches.iter_mut() {
+                branch.register(&token);
+            }
+            // Unregisters even when the wait unwinds on cancellation
+            let registered = Registered {
+                branches: &mut self.branches,
+                token: &token,
+            };
+            // An event may have been ready before the registration
+            let done = try_complete(registered.branches);
+            if !done {
+                token.waiter.wait_deadline(deadline);
+            }
+            drop(registered);
+            if done {
+                return;
+            }
+        }
+    }
+
+    fn try_complete(&mut self) -> bool {
+        try_complete(&mut self.branches)
+    }
+}
+
+fn try_complete(branches: &mut [Box<dyn Branch + '_>]) -> bool {
+    branches.iter_mut().any(|branch| branch.try_complete())
+}
+
+/// Unregisters a token from every branch on drop
+struct Registered<'a, 'b> {
+    branches: &'a mut [Box<dyn Branch + 'b>],
+    token: &'a Token,
+}
+
+impl Drop for Registered<'_, '_> {
+    fn drop(&mut self) {
+        for branch in self.branches.iter_mut() {
+            branch.unregister(self.token);
+        }
+    }
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Select<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Select")
+            .field("branches", &self.branches.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Waits for the first ready of several events and evaluates its branch
+///
+/// Each branch is `pattern = source => expression` where `source` implements
+/// [`Source`](crate::select::Source) and `pattern` is irrefutable. The optional
+/// `timeout(duration) => expression` branch runs when nothing is ready in time, the optional
+/// `default => expression` branch when nothing is ready right away.
+///
+/// The branch expressions run after the wait completed: they may borrow the same variables,
+/// `return`, `break` or `continue`.
+#[macro_export]
+macro_rules! select {
+    ($($branches:tt)+) => {
+        match $crate::__select!(@branch ($crate::select::Select::new()) $($branches)+) {
+            ::core::option::Option::Some(output) => output,
+            ::core::option::Option::None => ::core::unreachable!(),
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select {
+    (@branch ($select:expr)) => {{
+        $select.wait();
+        ::core::option::Option::None
+    }};
+    (@branch ($select:expr) timeout($timeout:expr) => $body:expr $(, $($rest:tt)*)?) => {{
+        let mut slot = ::core::option::Option::None;
+        let output = $crate::__select!(
+            @branch ($select.timeout($timeout, || slot = ::core::option::Option::Some(())))
+            $($($rest)*)?
+        );
+        match slot {
+            ::core::option::Option::Some(()) => ::core::option::Option::Some($body),
+            ::core::option::Option::None => output,
+        }
+    }};
+    (@branch ($select:expr) default => $body:expr $(, $($rest:tt)*)?) => {{
+        let mut slot = ::core::option::Option::None;
+        let output = $crate::__select!(
+            @branch ($select.default(|| slot = ::core::option::Option::Some(())))
+            $($($rest)*)?
+        );
+        match slot {
+            ::core::option::Option::Some(()) => ::core::option::Option::Some($body),
+            ::core::option::Option::None => output,
+        }
+    }};
+    (@branch ($select:expr) $pattern:pat = $source:expr => $body:expr $(, $($rest:tt)*)?) => {{
+        let mut slot = ::core::option::Option::None;
+        let output = $crate::__select!(
+            @branch ($select.on($source, |value| slot = ::core::option::Option::Some(value)))
+            $($($rest)*)?
+        );
+        match slot {
+            ::core::option::Option::Some(value) => {
+                let $pattern = value;
+                ::core::option::Option::Some($body)
+            }
+            ::core::option::Option::None => output,
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        net::unix::UnixStream,
+        spawn,
+        sync::{mpmc, mpsc},
+        time::sleep,
+        yield_now,
+    };
+    use std::{
+        cell::RefCell,
+        io::Write,
+        rc::Rc,
+        sync::{Arc, Weak},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_select_channels() {
+        crate::run(|| {
+            let (tx1, rx1) = mpsc::channel::<u32>();
+            let (tx2, rx2) = mpsc::channel::<&str>();
+            spawn(move || {
+                sleep(Duration::from_millis(5)).unwrap();
+                tx2.send("two").unwrap();
+                sleep(Duration::from_millis(5)).unwrap();
+                drop(tx1);
+            });
+            let mut events = Vec::new();
+            for _ in 0..2 {
+                crate::select! {
+                    n = &rx1 => events.push(format!("{n:?}")),
+                    s = &rx2 => events.push(format!("{s:?}")),
+                }
+            }
+            assert_eq!(events, ["Ok(\"two\")", "Err(RecvError)"]);
+        });
+    }
+
+    #[test]
+    fn test_select_timeout_default() {
+        crate::run(|| {
+            let (_tx, rx) = mpsc::channel::<u32>();
+            let timed_out = crate::select! {
+                _ = &rx => false,
+                timeout(Duration::from_millis(5)) => true,
+            };
+            assert!(timed_out);
+            let ready = crate::select! {
+                _ = &rx => true,
+                default => false,
+            };
+            assert!(!ready);
+
+            // The receiver left the wait queue of the channel
+            let (tx, rx) = mpsc::sync_channel(1);
+            let other = spawn(move || rx.recv().unwrap());
+            yield_now();
+            tx.send(1).unwrap();
+            assert_eq!(other.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn test_select_cancel() {
+        crate::run(|| {
+            let (tx, rx) = mpmc::channel::<u32>();
+            let (_tx2, rx2) = mpsc::channel::<u32>();
+            let their_rx = rx.clone();
+            let task = Rc::new(RefCell::new(Weak::new()));
+            let their_task = task.clone();
+            let selecting = spawn(move || {
+                *their_task.borrow_mut() = Arc::downgrade(&crate::runtime::current_task());
+                crate::select! {
+                    _ = &their_rx => (),
+                    _ = &rx2 => (),
+                }
+            });
+            yield_now();
+            let receiving = spawn(move || rx.recv().unwrap());
+            yield_now();
+            assert!(selecting.cancel());
+            assert!(selecting.join().is_err());
+            // The cancelled select left the wait queues, which no longer hold its task
+            assert!(task.borrow().upgrade().is_none());
+            tx.send(1).unwrap();
+            assert_eq!(receiving.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn test_select_readiness() {
+        crate::run(|| {
+            let (a, b) = UnixStream::pair().unwrap();
+            let (_tx, rx) = mpsc::channel::<u32>();
+            spawn(move || {
+                yield_now();
+                (&b).write_all(b"ping").unwrap();
+            });
+            let readable = crate::select! {
+                _ = &rx => false,
+                () = a.readable() => true,
+            };
+            assert!(readable);
+        });
+    }
+
+    #[test]
+    fn test_select_external_no_deadlock() {
+        let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+        let runtime = crate::runtime::Builder::new()
+            .detect_deadlocks()
+            .build()
+            .unwrap();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            (&b).write_all(b"ping").unwrap();
+        });
+        // The runtime is idle until the other thread writes
+        let readable = runtime.run(move || {
+            let a = UnixStream::from_std(a).unwrap();
+            let (_tx, rx) = mpsc::channel::<()>();
+            crate::select! {
+                _ = &rx => false,
+                () = a.readable() => true,
+            }
+        });
+        assert!(readable);
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! Unix signals delivered to coroutines
+//!
+//! The runtime blocks the requested signals on its thread and reads them from a signalfd
+//! registered in its reactor. A process-directed signal is only received when every thread of
+//! the process blocks it: the helper threads of the runtime block every asynchronous signal, the
+//! other threads of the application must block the received signals themselves (threads inherit
+//! the mask of their parent, so spawning them from a coroutine after the first `recv` is enough).
+//!
+//! Fault signals (`SIGSEGV`, `SIGBUS`, `SIGILL`, `SIGFPE`) are never blocked and keep going to
+//! their handlers, such as a stack overflow handler.
+
+use std::io;
+
+pub use libc::{
+    SIGALRM, SIGCHLD, SIGHUP, SIGINT, SIGPIPE, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2, SIGWINCH,
+};
+
+use crate::runtime;
+
+/// Suspends the current coroutine until `signal` is received
+///
+/// The signal stays blocked on the thread of the runtime from the first call on, until the
+/// runtime is dropped. Occurrences of a signal received while no coroutine waits for it are
+/// queued (merged by the kernel while not read from the signalfd).
+///
+/// Fails with `InvalidInput` for fault and uncatchable signals, and with `TimedOut` once the
+/// deadline of the enclosing [`timeout`](crate::timeout) scope is reached.
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn recv(signal: libc::c_int) -> io::Result<()> {
+    runtime::current_worker()
+        .expect("`recv` called outside of a coroutine runtime")
+        .signals()
+        .recv(signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawn, yield_now};
+
+    #[test]
+    fn test_signal_recv() {
+        crate::run(|| {
+            let waiter = spawn(|| recv(SIGUSR2));
+            yield_now();
+            // Thread-directed: the other test threads do not block the signal
+            let raise = || unsafe { libc::pthread_kill(libc::pthread_self(), SIGUSR2) };
+            assert_eq!(raise(), 0);
+            waiter.join().unwrap().unwrap();
+            // Queued while no coroutine waits
+            assert_eq!(raise(), 0);
+            recv(SIGUSR2).unwrap();
+
+            let err = recv(libc::SIGSEGV).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+}
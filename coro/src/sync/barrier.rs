@@ -0,0 +1,78 @@
+use std::{fmt, sync::Arc};
+
+use crate::runtime::{Blocker, ParkReason, Waiter};
+
+struct State {
+    count: usize,
+    waiters: Vec<Arc<Waiter>>,
+}
+
+/// A barrier suspending the current coroutine until `n` coroutines reached it
+pub struct Barrier {
+    n: usize,
+    state: std::sync::Mutex<State>,
+}
+
+/// Returned by [`Barrier::wait`], exactly one coroutine per generation is the leader
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl fmt::Debug for BarrierWaitResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BarrierWaitResult")
+            .field("is_leader", &self.0)
+            .finish()
+    }
+}
+
+impl Barrier {
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: std::sync::Mutex::new(State {
+                count: 0,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Suspends the current coroutine until `n` coroutines are waiting, the last one to arrive
+    /// is the leader and resumes the others
+    ///
+    /// # Panics
+    ///  - When the coroutine must wait and this is called outside of a coroutine
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut waiter = None;
+        let waiter = loop {
+            let mut state = self.state.lock().unwrap();
+            if state.count + 1 >= self.n {
+                state.count = 0;
+                for waiter in state.waiters.drain(..) {
+                    waiter.notify();
+                }
+                return BarrierWaitResult(true);
+            }
+            if let Some(waiter) = waiter.take() {
+                state.count += 1;
+                state.waiters.push(Arc::clone(&waiter));
+                break waiter;
+            }
+            drop(state);
+            // Built with the state unlocked: it panics outside of a coroutine
+            waiter = Some(Waiter::blocked_on(ParkReason::Barrier, Blocker::of(self)));
+        };
+        waiter.wait();
+        BarrierWaitResult(false)
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier").finish_non_exhaustive()
+    }
+}
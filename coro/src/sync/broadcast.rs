@@ -0,0 +1,274 @@
+//! A multi-producer, multi-consumer channel where every receiver sees every value.
+//!
+//! The channel keeps the last `capacity` values: sending never suspends, a receiver falling
+//! further behind skips the oldest values and is told how many it missed.
+
+use std::{collections::VecDeque, error, fmt, sync::Arc};
+
+use crate::{
+    runtime::{Blocker, ParkReason, WaitQueue, Waiter},
+    select::{Source, Token},
+};
+
+pub use std::sync::mpsc::SendError;
+
+/// Error returned by [`Receiver::recv`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvError {
+    /// Every sender was dropped and the receiver saw every value
+    Closed,
+    /// The receiver lagged behind and missed this many values, the next receive returns the
+    /// oldest value still held by the channel
+    Lagged(u64),
+}
+
+/// Error returned by [`Receiver::try_recv`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    /// The receiver saw every value sent so far
+    Empty,
+    /// Every sender was dropped and the receiver saw every value
+    Closed,
+    /// The receiver lagged behind and missed this many values
+    Lagged(u64),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => f.write_str("channel closed"),
+            RecvError::Lagged(n) => write!(f, "channel lagged by {n}"),
+        }
+    }
+}
+
+impl error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("channel empty"),
+            TryRecvError::Closed => f.write_str("channel closed"),
+            TryRecvError::Lagged(n) => write!(f, "channel lagged by {n}"),
+        }
+    }
+}
+
+impl error::Error for TryRecvError {}
+
+struct State<T> {
+    /// The values still held, `buffer[0]` is the value at position `head`
+    buffer: VecDeque<T>,
+    head: u64,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+    waiters: WaitQueue,
+}
+
+impl<T: Clone> State<T> {
+    fn recv(&self, next: &mut u64) -> Result<T, TryRecvError> {
+        if *next < self.head {
+            let missed = self.head - *next;
+            *next = self.head;
+            return Err(TryRecvError::Lagged(missed));
+        }
+        match self.buffer.get((*next - self.head) as usize) {
+            Some(t) => {
+                *next += 1;
+                Ok(t.clone())
+            }
+            None if self.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+struct Shared<T> {
+    state: std::sync::Mutex<State<T>>,
+}
+
+/// The sending half of a broadcast channel
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a broadcast channel
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    next: u64,
+}
+
+/// Creates a broadcast channel holding the last `capacity` values
+///
+/// # Panics
+///  - When `capacity` is 0
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "Broadcast channels need a capacity");
+    let shared = Arc::new(Shared {
+        state: std::sync::Mutex::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            head: 0,
+            capacity,
+            senders: 1,
+            receivers: 1,
+            waiters: WaitQueue::new(),
+        }),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends a value to every receiver, returns the number of receivers or fails when there is
+    /// none, never suspends
+    pub fn send(&self, t: T) -> Result<usize, SendError<T>> {
+        let (dropped, receivers) = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.receivers == 0 {
+                return Err(SendError(t));
+            }
+            let dropped = if state.buffer.len() == state.capacity {
+                state.head += 1;
+                state.buffer.pop_front()
+            } else {
+                None
+            };
+            state.buffer.push_back(t);
+            state.waiters.notify_all();
+            (dropped, state.receivers)
+        };
+        // Values may have arbitrary drop implementations, never run them under the lock
+        drop(dropped);
+        Ok(receivers)
+    }
+
+    /// Returns a receiver seeing the values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.head + state.buffer.len() as u64,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().unwrap().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.waiters.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Receives the next value, suspending the current coroutine until it is sent
+    ///
+    /// # Panics
+    ///  - When no value is available and this is called outside of a coroutine
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        let mut waiter = None;
+        loop {
+            let mut state = self.shared.state.lock().unwrap();
+            match state.recv(&mut self.next) {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Closed) => return Err(RecvError::Closed),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Empty) => {}
+            }
+            if let Some(waiter) = waiter.take() {
+                state.waiters.push(Arc::clone(&waiter));
+                drop(state);
+                waiter.wait();
+                continue;
+            }
+            drop(state);
+            // Built with the state unlocked: it panics outside of a coroutine
+            waiter = Some(Waiter::blocked_on(
+                ParkReason::Channel,
+                Blocker::of(&*self.shared),
+            ));
+        }
+    }
+
+    /// Receives the next value if it was sent, never suspends
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.shared.state.lock().unwrap().recv(&mut self.next)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns a receiver starting at the same position as this one
+    pub fn resubscribe(&self) -> Self {
+        self.shared.state.lock().unwrap().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receivers -= 1;
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T: Clone> Source for &mut Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_complete(&mut self) -> Option<Self::Output> {
+        match self.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Closed) => Some(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(n)) => Some(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    fn register(&mut self, token: &Token) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.waiters.push(token.waiter().clone());
+    }
+
+    fn unregister(&mut self, token: &Token) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.waiters.remove(token.waiter());
+    }
+
+    fn is_external(&self) -> bool {
+        false
+    }
+}
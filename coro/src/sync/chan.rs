@@ -0,0 +1,184 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError, TryRecvError, TrySendError},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::runtime::{Blocker, ParkReason, WaitQueue, Waiter};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: Option<usize>,
+    senders: usize,
+    receivers: usize,
+    recv_waiters: WaitQueue,
+    send_waiters: WaitQueue,
+}
+
+impl<T> State<T> {
+    fn push(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        if self.receivers == 0 {
+            return Err(TrySendError::Disconnected(t));
+        }
+        if self
+            .capacity
+            .is_some_and(|capacity| self.queue.len() >= capacity)
+        {
+            return Err(TrySendError::Full(t));
+        }
+        self.queue.push_back(t);
+        self.recv_waiters.notify_one();
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<T, TryRecvError> {
+        match self.queue.pop_front() {
+            Some(t) => {
+                if self.capacity.is_some() {
+                    self.send_waiters.notify_one();
+                }
+                Ok(t)
+            }
+            None if self.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+/// The queue shared by the senders and receivers of a channel.
+///
+/// Notifying a waiter only tells it to retry: a woken receiver may find the queue emptied by
+/// another one in the meantime, in which case it waits again.
+pub(crate) struct Chan<T> {
+    state: std::sync::Mutex<State<T>>,
+}
+
+impl<T> Chan<T> {
+    /// Returns a channel with one sender and one receiver, unbounded when `capacity` is `None`
+    pub(crate) fn new(capacity: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            state: std::sync::Mutex::new(State {
+                queue: VecDeque::new(),
+                capacity,
+                senders: 1,
+                receivers: 1,
+                recv_waiters: WaitQueue::new(),
+                send_waiters: WaitQueue::new(),
+            }),
+        })
+    }
+
+    pub(crate) fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.state.lock().unwrap().push(t)
+    }
+
+    /// Sends `t`, suspending the current coroutine while the channel is full, fails with
+    /// [`TrySendError::Full`] once `deadline` is reached
+    pub(crate) fn send(&self, t: T, deadline: Option<Instant>) -> Result<(), TrySendError<T>> {
+        let mut t = match self.try_send(t) {
+            Err(TrySendError::Full(t)) => t,
+            result => return result,
+        };
+        loop {
+            let waiter = Waiter::blocked_on(ParkReason::Channel, Blocker::of(self));
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.push(t) {
+                    Err(TrySendError::Full(v)) => t = v,
+                    result => return result,
+                }
+                state.send_waiters.push(waiter.clone());
+            }
+            if !waiter.wait_deadline(deadline) {
+                self.state.lock().unwrap().send_waiters.remove(&waiter);
+                return Err(TrySendError::Full(t));
+            }
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.state.lock().unwrap().pop()
+    }
+
+    /// Receives a value, suspending the current coroutine while the channel is empty
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        match self.try_recv() {
+            Ok(t) => return Ok(t),
+            Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            Err(TryRecvError::Empty) => {}
+        }
+        loop {
+            let waiter = Waiter::blocked_on(ParkReason::Channel, Blocker::of(self));
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.pop() {
+                    Ok(t) => return Ok(t),
+                    Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => {}
+                }
+                state.recv_waiters.push(waiter.clone());
+            }
+            if !waiter.wait_deadline(deadline) {
+                self.state.lock().unwrap().recv_waiters.remove(&waiter);
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+
+    /// Receives a value for a [`Select`](crate::select::Select) if one is available
+    pub(crate) fn select_recv(&self) -> Option<Result<T, RecvError>> {
+        match self.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    pub(crate) fn register_recv(&self, waiter: &Arc<Waiter>) {
+        self.state.lock().unwrap().recv_waiters.push(waiter.clone());
+    }
+
+    pub(crate) fn unregister_recv(&self, waiter: &Arc<Waiter>) {
+        let mut state = self.state.lock().unwrap();
+        // The waiter left the queue when notified, the value may still be there if it was
+        // resumed by another branch
+        if !state.recv_waiters.remove(waiter) && !state.queue.is_empty() {
+            state.recv_waiters.notify_one();
+        }
+    }
+
+    pub(crate) fn add_sender(&self) {
+        self.state.lock().unwrap().senders += 1;
+    }
+
+    pub(crate) fn add_receiver(&self) {
+        self.state.lock().unwrap().receivers += 1;
+    }
+
+    /// Drops a sender, the receivers are woken up when it was the last one
+    pub(crate) fn drop_sender(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.recv_waiters.notify_all();
+        }
+    }
+
+    /// Drops a receiver, the queued values are dropped with the last one
+    pub(crate) fn drop_receiver(&self) {
+        let queue = {
+            let mut state = self.state.lock().unwrap();
+            state.receivers -= 1;
+            if state.receivers != 0 {
+                return;
+            }
+            state.send_waiters.notify_all();
+            std::mem::take(&mut state.queue)
+        };
+        // Values may have arbitrary drop implementations, never run them under the lock
+        drop(queue);
+    }
+}
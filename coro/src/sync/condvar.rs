@@ -0,0 +1,114 @@
+use std::{
+    sync::LockResult,
+    time::{Duration, Instant},
+};
+
+use super::MutexGuard;
+use crate::runtime::{Blocker, ParkReason, WaitQueue, Waiter};
+
+/// Whether a timed wait on a [`Condvar`] returned because of its timeout
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable suspending the current coroutine instead of blocking its thread
+#[derive(Default)]
+pub struct Condvar {
+    waiters: std::sync::Mutex<WaitQueue>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: std::sync::Mutex::new(WaitQueue::new()),
+        }
+    }
+
+    /// Unlocks the mutex of `guard` and suspends the current coroutine until notified, then locks
+    /// the mutex again
+    ///
+    /// # Panics
+    ///  - When called outside of a coroutine
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let waiter = Waiter::blocked_on(ParkReason::Condvar, Blocker::of(self));
+        self.waiters.lock().unwrap().push(waiter.clone());
+        let mutex = guard.mutex();
+        drop(guard);
+        waiter.wait();
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns `false`
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like [`wait`](Self::wait) but gives up after `duration`
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        duration: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let waiter = Waiter::blocked_on(ParkReason::Condvar, Blocker::of(self));
+        self.waiters.lock().unwrap().push(waiter.clone());
+        let mutex = guard.mutex();
+        drop(guard);
+        let notified = waiter.wait_deadline(Instant::now().checked_add(duration));
+        if !notified {
+            self.waiters.lock().unwrap().remove(&waiter);
+        }
+        let result = WaitTimeoutResult(!notified);
+        match mutex.lock() {
+            Ok(guard) => Ok((guard, result)),
+            Err(err) => Err(std::sync::PoisonError::new((err.into_inner(), result))),
+        }
+    }
+
+    /// Waits until `condition` returns `false` or `duration` elapsed
+    pub fn wait_timeout_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        duration: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            if !condition(&mut *guard) {
+                return Ok((guard, WaitTimeoutResult(false)));
+            }
+            let timeout = match duration.checked_sub(start.elapsed()) {
+                Some(timeout) => timeout,
+                None => return Ok((guard, WaitTimeoutResult(true))),
+            };
+            guard = self.wait_timeout(guard, timeout)?.0;
+        }
+    }
+
+    /// Wakes up one coroutine waiting on this condition variable
+    pub fn notify_one(&self) {
+        self.waiters.lock().unwrap().notify_one();
+    }
+
+    /// Wakes up every coroutine waiting on this condition variable
+    pub fn notify_all(&self) {
+        self.waiters.lock().unwrap().notify_all();
+    }
+}
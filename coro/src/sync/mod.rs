@@ -0,0 +1,277 @@
+//! Synchronization primitives suspending the current coroutine instead of blocking its thread.
+//!
+//! The API mirrors `std::sync`: switching from the standard primitives is a matter of changing
+//! the imports. The uncontended paths never suspend and may be used outside of a coroutine.
+//!
+//! The channels of [`mpsc`], [`mpmc`], [`oneshot`] and [`broadcast`] are `Send`, their halves
+//! may be moved to coroutines running on other threads.
+
+mod barrier;
+pub mod broadcast;
+mod chan;
+mod condvar;
+pub mod mpmc;
+pub mod mpsc;
+mod mutex;
+pub mod oneshot;
+mod poison;
+mod rwlock;
+mod semaphore;
+
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use condvar::{Condvar, WaitTimeoutResult};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+pub use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawn, yield_now};
+    use std::{rc::Rc, sync::Arc, time::Duration};
+
+    #[test]
+    fn test_mutex() {
+        crate::run(|| {
+            let counter = Rc::new(Mutex::new(0));
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let counter = counter.clone();
+                    spawn(move || {
+                        for _ in 0..100 {
+                            let mut guard = counter.lock().unwrap();
+                            let value = *guard;
+                            // Suspending while holding the lock must not let anyone else in
+                            yield_now();
+                            *guard = value + 1;
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(*counter.lock().unwrap(), 800);
+            assert!(counter.try_lock().is_ok());
+        });
+    }
+
+    #[test]
+    fn test_mutex_poison() {
+        crate::run(|| {
+            let mutex = Arc::new(Mutex::new(0));
+            let their_mutex = mutex.clone();
+            let _ = spawn(move || {
+                let _guard = their_mutex.lock().unwrap();
+                panic!("poison");
+            })
+            .join();
+            assert!(mutex.is_poisoned());
+            assert!(mutex.lock().is_err());
+            mutex.clear_poison();
+            assert!(mutex.lock().is_ok());
+        });
+    }
+
+    #[test]
+    fn test_contended_outside_coroutine() {
+        let panics =
+            |f: &dyn Fn()| std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).is_err();
+        let mutex = Mutex::new(0);
+        let guard = mutex.lock().unwrap();
+        assert!(panics(&|| drop(mutex.lock())));
+        drop(guard);
+        assert!(!mutex.is_poisoned());
+        assert!(mutex.try_lock().is_ok());
+
+        let lock = RwLock::new(0);
+        let guard = lock.write().unwrap();
+        assert!(panics(&|| drop(lock.read())));
+        drop(guard);
+        assert!(lock.try_read().is_ok());
+
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire();
+        assert!(panics(&|| drop(semaphore.acquire())));
+        drop(permit);
+        assert!(semaphore.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_rwlock() {
+        crate::run(|| {
+            let lock = Rc::new(RwLock::new(Vec::new()));
+            let reader = lock.read().unwrap();
+            let writer = {
+                let lock = lock.clone();
+                spawn(move || lock.write().unwrap().push(1))
+            };
+            yield_now();
+            // The pending writer blocks new readers
+            assert!(lock.try_read().is_err());
+            assert!(reader.is_empty());
+            drop(reader);
+            writer.join().unwrap();
+            assert_eq!(*lock.read().unwrap(), vec![1]);
+        });
+    }
+
+    #[test]
+    fn test_condvar() {
+        crate::run(|| {
+            let pair = Rc::new((Mutex::new(false), Condvar::new()));
+            let their_pair = pair.clone();
+            let waiter = spawn(move || {
+                let (lock, cvar) = &*their_pair;
+                let ready = cvar
+                    .wait_while(lock.lock().unwrap(), |ready| !*ready)
+                    .unwrap();
+                *ready
+            });
+            yield_now();
+            *pair.0.lock().unwrap() = true;
+            pair.1.notify_all();
+            assert!(waiter.join().unwrap());
+
+            let (lock, cvar) = &*pair;
+            let (_guard, result) = cvar
+                .wait_timeout(lock.lock().unwrap(), Duration::from_millis(10))
+                .unwrap();
+            assert!(result.timed_out());
+        });
+    }
+
+    #[test]
+    fn test_semaphore_barrier() {
+        crate::run(|| {
+            let semaphore = Rc::new(Semaphore::new(2));
+            let barrier = Rc::new(Barrier::new(3));
+            let handles: Vec<_> = (0..3)
+                .map(|_| {
+                    let semaphore = semaphore.clone();
+                    let barrier = barrier.clone();
+                    spawn(move || {
+                        let permit = semaphore.acquire();
+                        yield_now();
+                        drop(permit);
+                        barrier.wait().is_leader()
+                    })
+                })
+                .collect();
+            let leaders = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|leader| *leader)
+                .count();
+            assert_eq!(leaders, 1);
+            assert_eq!(semaphore.available_permits(), 2);
+            assert!(semaphore.try_acquire_many(3).is_none());
+        });
+    }
+
+    #[test]
+    fn test_sync_channel() {
+        crate::run(|| {
+            let (tx, rx) = mpsc::sync_channel(2);
+            let producer = spawn(move || {
+                for i in 0..10 {
+                    tx.send(i).unwrap();
+                }
+            });
+            yield_now();
+            // The producer is suspended on the full channel
+            assert!(!producer.is_finished());
+            assert_eq!(rx.iter().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+            producer.join().unwrap();
+            assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+            assert_eq!(
+                rx.recv_timeout(Duration::from_millis(1)),
+                Err(mpsc::RecvTimeoutError::Disconnected)
+            );
+        });
+    }
+
+    #[test]
+    fn test_channel_across_threads() {
+        let (tx, rx) = mpmc::sync_channel(4);
+        let (done_tx, done_rx) = oneshot::channel();
+        let consumer = std::thread::spawn(move || {
+            crate::run(move || {
+                let handles: Vec<_> = (0..2)
+                    .map(|_| {
+                        let rx = rx.clone();
+                        spawn(move || rx.iter().sum::<u64>())
+                    })
+                    .collect();
+                drop(rx);
+                let sum = handles.into_iter().map(|h| h.join().unwrap()).sum::<u64>();
+                done_tx.send(sum).unwrap();
+            })
+        });
+        crate::run(move || {
+            for i in 0..1000 {
+                tx.send(i).unwrap();
+            }
+            drop(tx);
+            assert_eq!(done_rx.recv(), Ok(499500));
+        });
+        consumer.join().unwrap();
+    }
+
+    #[test]
+    fn test_mpmc_across_runtimes() {
+        let (tx, rx) = mpmc::sync_channel(4);
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let rx = rx.clone();
+                std::thread::spawn(move || crate::run(move || rx.iter().sum::<u64>()))
+            })
+            .collect();
+        drop(rx);
+        // The producer is suspended while the channel is full, and woken up by the consumers
+        crate::run(move || {
+            for i in 0..1000 {
+                tx.send(i).unwrap();
+            }
+        });
+        let sum: u64 = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        assert_eq!(sum, 499500);
+    }
+
+    #[test]
+    fn test_oneshot() {
+        crate::run(|| {
+            let (tx, mut rx) = oneshot::channel::<u32>();
+            assert_eq!(
+                rx.recv_timeout(Duration::from_millis(1)),
+                Err(oneshot::RecvTimeoutError::Timeout)
+            );
+            spawn(move || tx.send(7).unwrap());
+            assert_eq!(rx.recv(), Ok(7));
+
+            let (tx, rx) = oneshot::channel::<u32>();
+            spawn(move || drop(tx));
+            assert_eq!(rx.recv(), Err(oneshot::RecvError));
+        });
+    }
+
+    #[test]
+    fn test_broadcast() {
+        crate::run(|| {
+            let (tx, mut rx) = broadcast::channel(2);
+            let mut late = tx.subscribe();
+            let reader = spawn(move || (rx.recv().unwrap(), rx.recv().unwrap()));
+            yield_now();
+            assert_eq!(tx.send(1), Ok(2));
+            assert_eq!(tx.send(2), Ok(2));
+            assert_eq!(reader.join().unwrap(), (1, 2));
+            tx.send(3).unwrap();
+            assert_eq!(late.recv(), Err(broadcast::RecvError::Lagged(1)));
+            assert_eq!(late.recv(), Ok(2));
+            assert_eq!(late.recv(), Ok(3));
+            drop(tx);
+            assert_eq!(late.recv(), Err(broadcast::RecvError::Closed));
+        });
+    }
+}
@@ -0,0 +1,157 @@
+//! Multi-producer, multi-consumer FIFO queues suspending the current coroutine instead of
+//! blocking its thread.
+//!
+//! Both halves can be cloned, each value is received by exactly one receiver.
+//!
+//! A runtime runs all its coroutines on one thread: to spread the consumers over several
+//! threads, move clones of the receiver to runtimes running on those threads. A coroutine
+//! suspended on the channel is woken up by the coroutines of any runtime.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::chan::Chan;
+use crate::select::{Source, Token};
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+/// The sending half of a channel
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// The receiving half of a channel
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// Creates an unbounded channel, sending never suspends
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let chan = Chan::new(None);
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates a channel holding at most `bound` values, sending suspends while it is full
+///
+/// # Panics
+///  - When `bound` is 0, rendezvous channels are not supported
+pub fn sync_channel<T>(bound: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(bound > 0, "Rendezvous channels are not supported");
+    let chan = Chan::new(Some(bound));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, suspending the current coroutine while the channel is full, fails when
+    /// every receiver was dropped
+    ///
+    /// # Panics
+    ///  - When the channel is full and this is called outside of a coroutine
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.chan.send(t, None).map_err(|err| match err {
+            TrySendError::Full(t) | TrySendError::Disconnected(t) => SendError(t),
+        })
+    }
+
+    /// Sends a value if the channel is not full, never suspends
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(t)
+    }
+
+    /// Like [`send`](Self::send) but gives up after `timeout`, returning
+    /// [`TrySendError::Full`] with the value
+    pub fn send_timeout(&self, t: T, timeout: Duration) -> Result<(), TrySendError<T>> {
+        self.chan.send(t, Instant::now().checked_add(timeout))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.add_sender();
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives a value, suspending the current coroutine while the channel is empty, fails once
+    /// the channel is empty and every sender was dropped
+    ///
+    /// # Panics
+    ///  - When the channel is empty and this is called outside of a coroutine
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.chan.recv(None).map_err(|_| RecvError)
+    }
+
+    /// Receives a value if one is available, never suspends
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    /// Like [`recv`](Self::recv) but gives up after `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.chan.recv(Instant::now().checked_add(timeout))
+    }
+
+    /// Returns an iterator receiving values until every sender was dropped
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.recv().ok())
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.chan.add_receiver();
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.drop_receiver();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> Source for &Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_complete(&mut self) -> Option<Self::Output> {
+        self.chan.select_recv()
+    }
+
+    fn register(&mut self, token: &Token) {
+        self.chan.register_recv(token.waiter());
+    }
+
+    fn unregister(&mut self, token: &Token) {
+        self.chan.unregister_recv(token.waiter());
+    }
+
+    fn is_external(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, LockResult, TryLockError, TryLockResult},
+};
+
+use super::poison;
+use crate::runtime::{self, current_holder, Blocker, CoroutineId, ParkReason, Waiter};
+
+struct State {
+    locked: bool,
+    /// The coroutine holding the lock, reported by deadlocks
+    owner: Option<CoroutineId>,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+impl State {
+    /// # Safety
+    ///  - `state` must point to the live state of a mutex
+    unsafe fn owner(state: *const ()) -> Option<CoroutineId> {
+        let state = unsafe { &*(state as *const std::sync::Mutex<State>) };
+        state.lock().unwrap().owner
+    }
+}
+
+/// A mutual exclusion primitive suspending the current coroutine instead of blocking its thread.
+///
+/// The lock is handed over to the waiters in FIFO order.
+pub struct Mutex<T: ?Sized> {
+    state: std::sync::Mutex<State>,
+    poison: poison::Flag,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// The scoped lock of a [`Mutex`], unlocks it on drop
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a Mutex<T>,
+    poison: poison::Guard,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(t: T) -> Self {
+        Self {
+            state: std::sync::Mutex::new(State {
+                locked: false,
+                owner: None,
+                waiters: VecDeque::new(),
+            }),
+            poison: poison::Flag::new(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.data.into_inner();
+        poison::map_result(poisoned, data)
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquires the mutex, suspending the current coroutine until it is available
+    ///
+    /// # Panics
+    ///  - When the mutex is contended and this is called outside of a coroutine
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        runtime::preemption_point();
+        let mut waiter = None;
+        loop {
+            let mut state = self.state.lock().unwrap();
+            if !state.locked {
+                state.locked = true;
+                state.owner = current_holder();
+                break;
+            }
+            if let Some(waiter) = waiter.take() {
+                state.waiters.push_back(Arc::clone(&waiter));
+                drop(state);
+                // The unlocking coroutine handed the lock over
+                waiter.wait();
+                break;
+            }
+            drop(state);
+            // Built with the state unlocked: it panics outside of a coroutine
+            // Safety: the waiter borrows the mutex while it waits
+            let blocker = unsafe { Blocker::held(&self.state, State::owner) };
+            waiter = Some(Waiter::blocked_on(ParkReason::Mutex, blocker));
+        }
+        MutexGuard::new(self)
+    }
+
+    /// Acquires the mutex if it is available, never suspends
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.locked {
+                return Err(TryLockError::WouldBlock);
+            }
+            state.locked = true;
+            state.owner = current_holder();
+        }
+        Ok(MutexGuard::new(self)?)
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        poison::map_result(poisoned, self.data.get_mut())
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.waiters.pop_front() {
+            if waiter.notify() {
+                state.owner = Some(waiter.task_id());
+                return;
+            }
+        }
+        state.locked = false;
+        state.owner = None;
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(t: T) -> Self {
+        Self::new(t)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.poison.get());
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(lock: &'a Mutex<T>) -> LockResult<Self> {
+        let poison = lock.poison.guard();
+        poison::map_result(lock.poison.get(), Self { lock, poison })
+    }
+
+    /// Returns the mutex of the guard, used by [`Condvar`](super::Condvar) to unlock it
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+        self.lock.unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
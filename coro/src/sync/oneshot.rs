@@ -0,0 +1,182 @@
+//! A channel sending a single value between two coroutines
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    runtime::{Blocker, ParkReason, WaitQueue, Waiter},
+    select::{Source, Token},
+};
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+
+struct State<T> {
+    value: Option<T>,
+    sender: bool,
+    receiver: bool,
+    waiters: WaitQueue,
+}
+
+struct Inner<T> {
+    state: std::sync::Mutex<State<T>>,
+}
+
+impl<T> Inner<T> {
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        match state.value.take() {
+            Some(t) => Ok(t),
+            None if state.sender => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let waiter = Waiter::blocked_on(ParkReason::Channel, Blocker::of(self));
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.value.is_some() || !state.sender {
+                    continue;
+                }
+                state.waiters.push(waiter.clone());
+            }
+            if !waiter.wait_deadline(deadline) {
+                self.state.lock().unwrap().waiters.remove(&waiter);
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+}
+
+/// The sending half of a oneshot channel
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The receiving half of a oneshot channel
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Creates a channel sending a single value
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: std::sync::Mutex::new(State {
+            value: None,
+            sender: true,
+            receiver: true,
+            waiters: WaitQueue::new(),
+        }),
+    });
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends the value, returns it back when the receiver was dropped
+    pub fn send(self, t: T) -> Result<(), T> {
+        let mut state = self.inner.state.lock().unwrap();
+        if !state.receiver {
+            return Err(t);
+        }
+        state.value = Some(t);
+        state.waiters.notify_all();
+        Ok(())
+    }
+
+    /// Returns `true` when the receiver was dropped
+    pub fn is_closed(&self) -> bool {
+        !self.inner.state.lock().unwrap().receiver
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.sender = false;
+        state.waiters.notify_all();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives the value, suspending the current coroutine until it is sent, fails when the
+    /// sender was dropped without sending
+    ///
+    /// # Panics
+    ///  - When the value was not sent yet and this is called outside of a coroutine
+    pub fn recv(self) -> Result<T, RecvError> {
+        self.inner.recv(None).map_err(|_| RecvError)
+    }
+
+    /// Receives the value if it was sent, never suspends
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// Like [`recv`](Self::recv) but gives up after `timeout`, the receiver can be used again
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Instant::now().checked_add(timeout))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.receiver = false;
+            state.value.take()
+        };
+        drop(value);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> Source for &mut Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_complete(&mut self) -> Option<Self::Output> {
+        match self.inner.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    fn register(&mut self, token: &Token) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.waiters.push(token.waiter().clone());
+    }
+
+    fn unregister(&mut self, token: &Token) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.waiters.remove(token.waiter());
+    }
+
+    fn is_external(&self) -> bool {
+        false
+    }
+}
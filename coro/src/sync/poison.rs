@@ -0,0 +1,53 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LockResult, PoisonError,
+    },
+    thread,
+};
+
+/// Set when a coroutine panics while holding a lock
+pub(super) struct Flag(AtomicBool);
+
+/// Whether the thread was already panicking when the lock was acquired
+pub(super) struct Guard {
+    panicking: bool,
+}
+
+impl Flag {
+    pub(super) const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    #[inline]
+    pub(super) fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(super) fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(super) fn guard(&self) -> Guard {
+        Guard {
+            panicking: thread::panicking(),
+        }
+    }
+
+    #[inline]
+    pub(super) fn done(&self, guard: &Guard) {
+        if !guard.panicking && thread::panicking() {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+pub(super) fn map_result<T>(poisoned: bool, t: T) -> LockResult<T> {
+    if poisoned {
+        Err(PoisonError::new(t))
+    } else {
+        Ok(t)
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, LockResult, TryLockError, TryLockResult},
+};
+
+use super::poison;
+use crate::runtime::{self, current_holder, Blocker, CoroutineId, ParkReason, Waiter};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct State {
+    readers: usize,
+    writer: bool,
+    /// The coroutine holding the write lock, reported by deadlocks
+    owner: Option<CoroutineId>,
+    waiters: VecDeque<(Arc<Waiter>, Access)>,
+}
+
+impl State {
+    /// # Safety
+    ///  - `state` must point to the live state of a lock
+    unsafe fn owner(state: *const ()) -> Option<CoroutineId> {
+        let state = unsafe { &*(state as *const std::sync::Mutex<State>) };
+        state.lock().unwrap().owner
+    }
+
+    /// Hands the lock over to the waiters at the front of the queue
+    fn grant(&mut self) {
+        while let Some((waiter, access)) = self.waiters.front() {
+            match access {
+                Access::Write if self.readers == 0 && !self.writer => {
+                    let waiter = waiter.clone();
+                    self.waiters.pop_front();
+                    if waiter.notify() {
+                        self.writer = true;
+                        self.owner = Some(waiter.task_id());
+                        return;
+                    }
+                }
+                Access::Read if !self.writer => {
+                    let waiter = waiter.clone();
+                    self.waiters.pop_front();
+                    if waiter.notify() {
+                        self.readers += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// A reader-writer lock suspending the current coroutine instead of blocking its thread.
+///
+/// Waiters are served in FIFO order: a pending writer blocks the readers arriving after it.
+pub struct RwLock<T: ?Sized> {
+    state: std::sync::Mutex<State>,
+    poison: poison::Flag,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+/// A shared scoped lock of a [`RwLock`]
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+
+/// An exclusive scoped lock of a [`RwLock`]
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    poison: poison::Guard,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(t: T) -> Self {
+        Self {
+            state: std::sync::Mutex::new(State {
+                readers: 0,
+                writer: false,
+                owner: None,
+                waiters: VecDeque::new(),
+            }),
+            poison: poison::Flag::new(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.data.into_inner();
+        poison::map_result(poisoned, data)
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks with shared read access, suspending the current coroutine until it is available
+    ///
+    /// # Panics
+    ///  - When the lock is contended and this is called outside of a coroutine
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        self.acquire(Access::Read);
+        poison::map_result(self.poison.get(), RwLockReadGuard { lock: self })
+    }
+
+    /// Locks with exclusive write access, suspending the current coroutine until it is available
+    ///
+    /// # Panics
+    ///  - When the lock is contended and this is called outside of a coroutine
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        self.acquire(Access::Write);
+        self.write_guard()
+    }
+
+    /// Locks with shared read access if it is available, never suspends
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.writer || !state.waiters.is_empty() {
+                return Err(TryLockError::WouldBlock);
+            }
+            state.readers += 1;
+        }
+        Ok(poison::map_result(
+            self.poison.get(),
+            RwLockReadGuard { lock: self },
+        )?)
+    }
+
+    /// Locks with exclusive write access if it is available, never suspends
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.writer || state.readers != 0 {
+                return Err(TryLockError::WouldBlock);
+            }
+            state.writer = true;
+            state.owner = current_holder();
+        }
+        Ok(self.write_guard()?)
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        poison::map_result(poisoned, self.data.get_mut())
+    }
+
+    fn acquire(&self, access: Access) {
+        runtime::preemption_point();
+        let mut waiter = None;
+        loop {
+            let mut state = self.state.lock().unwrap();
+            let available = match access {
+                Access::Read => !state.writer && state.waiters.is_empty(),
+                Access::Write => !state.writer && state.readers == 0,
+            };
+            if available {
+                match access {
+                    Access::Read => state.readers += 1,
+                    Access::Write => {
+                        state.writer = true;
+                        state.owner = current_holder();
+                    }
+                }
+                return;
+            }
+            if let Some(waiter) = waiter.take() {
+                state.waiters.push_back((Arc::clone(&waiter), access));
+                drop(state);
+                // The releasing coroutine handed the lock over
+                waiter.wait();
+                return;
+            }
+            drop(state);
+            // Built with the state unlocked: it panics outside of a coroutine
+            // Safety: the waiter borrows the lock while it waits
+            let blocker = unsafe { Blocker::held(&self.state, State::owner) };
+            waiter = Some(Waiter::blocked_on(ParkReason::RwLock, blocker));
+        }
+    }
+
+    fn write_guard(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let poison = self.poison.guard();
+        poison::map_result(self.poison.get(), RwLockWriteGuard { lock: self, poison })
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(t: T) -> Self {
+        Self::new(t)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.poison.get());
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock().unwrap();
+        state.readers -= 1;
+        if state.readers == 0 {
+            state.grant();
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+        let mut state = self.lock.state.lock().unwrap();
+        state.writer = false;
+        state.owner = None;
+        state.grant();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
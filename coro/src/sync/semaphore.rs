@@ -0,0 +1,153 @@
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+use crate::runtime::{self, Blocker, ParkReason, Waiter};
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<(Arc<Waiter>, usize)>,
+}
+
+impl State {
+    /// Hands the available permits over to the waiters at the front of the queue
+    fn grant(&mut self) {
+        while let Some((waiter, n)) = self.waiters.front() {
+            if *n > self.permits {
+                return;
+            }
+            let (waiter, n) = (waiter.clone(), *n);
+            self.waiters.pop_front();
+            if waiter.notify() {
+                self.permits -= n;
+            }
+        }
+    }
+}
+
+/// A counting semaphore suspending the current coroutine instead of blocking its thread.
+///
+/// Permits are handed over to the waiters in FIFO order.
+pub struct Semaphore {
+    state: std::sync::Mutex<State>,
+}
+
+/// Permits acquired from a [`Semaphore`], released on drop
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: std::sync::Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// Adds `n` permits to the semaphore
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.permits += n;
+        state.grant();
+    }
+
+    /// Acquires a permit, suspending the current coroutine until one is available
+    ///
+    /// # Panics
+    ///  - When the semaphore is contended and this is called outside of a coroutine
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Acquires `n` permits, suspending the current coroutine until they are available
+    ///
+    /// # Panics
+    ///  - When the semaphore is contended and this is called outside of a coroutine
+    pub fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        runtime::preemption_point();
+        let mut waiter = None;
+        loop {
+            let mut state = self.state.lock().unwrap();
+            if state.waiters.is_empty() && state.permits >= n {
+                state.permits -= n;
+                break;
+            }
+            if let Some(waiter) = waiter.take() {
+                state.waiters.push_back((Arc::clone(&waiter), n));
+                drop(state);
+                // The releasing coroutine handed the permits over
+                waiter.wait();
+                break;
+            }
+            drop(state);
+            // Built with the state unlocked: it panics outside of a coroutine
+            waiter = Some(Waiter::blocked_on(ParkReason::Semaphore, Blocker::of(self)));
+        }
+        SemaphorePermit {
+            semaphore: self,
+            permits: n,
+        }
+    }
+
+    /// Acquires a permit if one is available, never suspends
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Acquires `n` permits if they are available, never suspends
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiters.is_empty() && state.permits >= n {
+            state.permits -= n;
+            Some(SemaphorePermit {
+                semaphore: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Returns the number of permits held
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Drops the permits without releasing them to the semaphore
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits != 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+impl fmt::Debug for SemaphorePermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphorePermit")
+            .field("permits", &self.permits)
+            .finish()
+    }
+}
@@ -0,0 +1,208 @@
+//! Sleeps and timeouts for coroutines
+
+use std::{
+    io, panic,
+    time::{Duration, Instant},
+};
+
+use crate::runtime::{self, Cancelled, Expired, Interrupt, ParkReason};
+
+/// Suspends the current coroutine for at least `duration`, fails when the coroutine is
+/// cancelled
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn sleep(duration: Duration) -> Result<(), Cancelled> {
+    sleep_until(
+        Instant::now()
+            .checked_add(duration)
+            .unwrap_or_else(far_future),
+    )
+}
+
+/// Roughly 30 years from now, used when a deadline overflows `Instant`
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(86400 * 365 * 30)
+}
+
+/// Suspends the current coroutine until `deadline`, fails when the coroutine is cancelled
+///
+/// Sleeping past the deadline of an enclosing [`timeout`] unwinds to it.
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn sleep_until(deadline: Instant) -> Result<(), Cancelled> {
+    let task = runtime::current_task();
+    let scope = task.deadline().filter(|scope| *scope < deadline);
+    loop {
+        if task.is_cancelled() {
+            return Err(task.deliver_cancel());
+        }
+        if runtime::park_until(scope.unwrap_or(deadline), ParkReason::Sleep) {
+            if scope.is_some() {
+                Interrupt::Expired.unwind();
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Runs `f` in the current coroutine until `duration` elapsed, then fails with
+/// [`io::ErrorKind::TimedOut`]
+///
+/// Once the deadline is reached, the I/O calls of `f` fail with `TimedOut`, and the other
+/// suspending calls (locks, channels, joins, sleeps...) unwind `f` which is then left, like
+/// for a cancellation (see [`Cancelled`]). Timeouts nest: the earliest deadline applies.
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn timeout<F, T>(duration: Duration, f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    match Instant::now().checked_add(duration) {
+        Some(deadline) => timeout_at(deadline, f),
+        None => f(),
+    }
+}
+
+/// Like [`timeout`] but with a deadline instead of a duration
+///
+/// # Panics
+///  - When called outside of a coroutine
+pub fn timeout_at<F, T>(deadline: Instant, f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    struct Restore(Option<Instant>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            runtime::current_task().set_deadline(self.0);
+        }
+    }
+
+    let task = runtime::current_task();
+    let outer = task.deadline();
+    task.set_deadline(Some(outer.map_or(deadline, |outer| outer.min(deadline))));
+    let _restore = Restore(outer);
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        // An earlier deadline of an enclosing scope unwinds to that scope
+        Err(payload) if payload.is::<Expired>() && Instant::now() >= deadline => {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::unix::UnixStream;
+    use std::{cell::RefCell, io::Read, rc::Rc};
+
+    #[test]
+    fn test_sleep_order() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let their_order = order.clone();
+        let start = Instant::now();
+        crate::run(move || {
+            let handles: Vec<_> = [30u64, 10, 20]
+                .into_iter()
+                .map(|ms| {
+                    let order = their_order.clone();
+                    crate::spawn(move || {
+                        sleep(Duration::from_millis(ms)).unwrap();
+                        order.borrow_mut().push(ms);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(*order.borrow(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        crate::run(|| {
+            let (mut a, _b) = UnixStream::pair().unwrap();
+            let start = Instant::now();
+            let mut buf = [0u8; 1];
+            let err = timeout(Duration::from_millis(20), || a.read(&mut buf)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(start.elapsed() >= Duration::from_millis(20));
+
+            // The deadline does not outlive its scope
+            let (mut a, mut b) = UnixStream::pair().unwrap();
+            let writer = crate::spawn(move || {
+                sleep(Duration::from_millis(10)).unwrap();
+                std::io::Write::write_all(&mut b, b"x").unwrap();
+            });
+            assert_eq!(a.read(&mut buf).unwrap(), 1);
+            writer.join().unwrap();
+
+            let value = timeout(Duration::from_secs(10), || Ok(42)).unwrap();
+            assert_eq!(value, 42);
+        });
+    }
+
+    #[test]
+    fn test_timeout_wait() {
+        use crate::sync::{mpsc, Mutex};
+
+        crate::run(|| {
+            let mutex = Rc::new(Mutex::new(0));
+            let guard = mutex.lock().unwrap();
+            let their_mutex = mutex.clone();
+            let start = Instant::now();
+            let err = timeout(Duration::from_millis(20), || {
+                let _guard = their_mutex.lock().unwrap();
+                Ok(())
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            // The expired waiter does not take the lock
+            drop(guard);
+            assert!(mutex.try_lock().is_ok());
+
+            let (tx, rx) = mpsc::channel::<u32>();
+            let err = timeout(Duration::from_millis(10), || Ok(rx.recv())).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            tx.send(1).unwrap();
+            assert_eq!(rx.recv(), Ok(1));
+
+            let err = timeout(Duration::from_millis(10), || {
+                sleep(Duration::from_secs(10)).unwrap();
+                Ok(())
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        });
+    }
+
+    #[test]
+    fn test_timeout_nested() {
+        crate::run(|| {
+            let left_inner = Rc::new(RefCell::new(false));
+            let their_left_inner = left_inner.clone();
+            let start = Instant::now();
+            let err = timeout(Duration::from_millis(20), move || {
+                let inner = timeout(Duration::from_secs(10), || {
+                    sleep(Duration::from_secs(10)).unwrap();
+                    Ok(())
+                });
+                *their_left_inner.borrow_mut() = true;
+                inner
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(start.elapsed() < Duration::from_secs(10));
+            // The outer deadline unwound through the inner scope
+            assert!(!*left_inner.borrow());
+        });
+    }
+}
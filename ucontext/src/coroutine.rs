@@ -0,0 +1,196 @@
+use std::{any::Any, cell::Cell, fmt, marker::PhantomData, panic, ptr::NonNull, rc::Rc, thread};
+
+use crate::{ContextId, ContextState, InnerErazed, UContext, CURRENT_CTX};
+
+/// What [`Coroutine::resume`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resumed<O> {
+    /// The coroutine called [`Suspender::suspend`]
+    Suspended,
+    /// The coroutine returned
+    Returned(O),
+}
+
+/// A coroutine resumed by whoever calls [`resume`](Coroutine::resume), which gets the control
+/// back when the coroutine suspends itself or returns
+///
+/// ```
+/// use ucontext::{Coroutine, Resumed};
+///
+/// let mut counter = Coroutine::new(|suspender| {
+///     for _ in 0..2 {
+///         suspender.suspend();
+///     }
+///     "done"
+/// });
+/// assert_eq!(counter.resume(), Resumed::Suspended);
+/// assert_eq!(counter.resume(), Resumed::Suspended);
+/// assert_eq!(counter.resume(), Resumed::Returned("done"));
+/// assert!(counter.is_done());
+/// ```
+///
+/// The root context of the thread is created on the first resume when needed, and coroutines
+/// may resume other coroutines. A panic of the coroutine is propagated by `resume`. Dropping a
+/// suspended coroutine releases its stack without unwinding it: the values living on it are
+/// leaked.
+pub struct Coroutine<O> {
+    ctx: UContext,
+    output: Rc<Cell<Option<thread::Result<O>>>>,
+}
+
+/// Suspends its coroutine, passed to the coroutine function
+pub struct Suspender {
+    ctx: NonNull<InnerErazed>,
+    /// Only usable on the coroutine
+    _local: PhantomData<*const ()>,
+}
+
+impl<O: 'static> Coroutine<O> {
+    /// Returns a coroutine running `f` with a default-sized stack, see
+    /// [`UContext::default_size`]
+    ///
+    /// # Panics
+    ///  - When the coroutine cannot be allocated
+    pub fn new<F>(f: F) -> Self
+    where
+        F: FnOnce(&Suspender) -> O + 'static,
+    {
+        Self::with_stack_size(f, UContext::default_size())
+    }
+
+    /// Returns a coroutine running `f` with a stack of at least `stack_size` bytes
+    ///
+    /// # Panics
+    ///  - When the coroutine cannot be allocated
+    pub fn with_stack_size<F>(f: F, stack_size: usize) -> Self
+    where
+        F: FnOnce(&Suspender) -> O + 'static,
+    {
+        let output = Rc::new(Cell::new(None));
+        let their_output = output.clone();
+        let ctx = UContext::pinned(
+            move || {
+                let suspender = Suspender {
+                    ctx: InnerErazed::current(),
+                    _local: PhantomData,
+                };
+                let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f(&suspender)));
+                their_output.set(Some(result));
+            },
+            stack_size,
+        )
+        .expect("Failed to allocate a coroutine");
+        Self { ctx, output }
+    }
+}
+
+impl<O> Coroutine<O> {
+    /// Runs the coroutine until it suspends itself or returns
+    ///
+    /// # Panics
+    ///  - When the coroutine already returned
+    ///  - When the coroutine is running, e.g. resumed from itself
+    ///  - When its stack cannot be allocated
+    ///  - When the coroutine panics
+    pub fn resume(&mut self) -> Resumed<O> {
+        match self.ctx.state() {
+            ContextState::Done => panic!("Resumed the finished coroutine {}", self.ctx.id()),
+            ContextState::Created => {
+                assert!(self.ctx.init(), "Failed to allocate a coroutine stack");
+            }
+            ContextState::Started => {}
+        }
+        assert!(
+            !self.ctx.is_running(),
+            "Resumed the running coroutine {}",
+            self.ctx.id()
+        );
+        self.ctx.resume();
+        match self.output.take() {
+            None => Resumed::Suspended,
+            Some(Ok(output)) => Resumed::Returned(output),
+            Some(Err(payload)) => panic::resume_unwind(payload as Box<dyn Any + Send>),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_done(&self) -> bool {
+        self.ctx.state() == ContextState::Done
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> ContextId {
+        self.ctx.id()
+    }
+}
+
+impl<O> fmt::Debug for Coroutine<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Coroutine")
+            .field("id", &self.ctx.id())
+            .field("state", &self.ctx.state())
+            .finish()
+    }
+}
+
+impl Suspender {
+    /// Returns the control to whoever resumed the coroutine, until it is resumed again
+    ///
+    /// # Panics
+    ///  - When called from another coroutine than its own
+    pub fn suspend(&self) {
+        assert!(
+            std::ptr::eq(CURRENT_CTX.get(), self.ctx.as_ptr()),
+            "Suspended a coroutine from another context"
+        );
+        UContext::suspend();
+    }
+}
+
+impl fmt::Debug for Suspender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Suspender")
+            .field("id", &unsafe { self.ctx.as_ref() }.id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_nested_coroutines() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let their_log = log.clone();
+        let mut outer = Coroutine::new(move |suspender| {
+            let log = their_log.clone();
+            let mut inner = Coroutine::new(move |suspender| {
+                log.borrow_mut().push("inner 1");
+                suspender.suspend();
+                log.borrow_mut().push("inner 2");
+                2
+            });
+            assert_eq!(inner.resume(), Resumed::Suspended);
+            // The inner coroutine came back here, not to the thread
+            their_log.borrow_mut().push("outer");
+            suspender.suspend();
+            inner.resume()
+        });
+        assert_eq!(outer.resume(), Resumed::Suspended);
+        assert_eq!(outer.resume(), Resumed::Returned(Resumed::Returned(2)));
+        assert_eq!(*log.borrow(), ["inner 1", "outer", "inner 2"]);
+    }
+
+    #[test]
+    fn test_resume_checks() {
+        let mut coroutine = Coroutine::new(|_| panic!("boom"));
+        let payload =
+            panic::catch_unwind(panic::AssertUnwindSafe(|| coroutine.resume())).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        assert!(coroutine.is_done());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| coroutine.resume()));
+        assert!(result.is_err());
+    }
+}
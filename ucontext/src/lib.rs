@@ -1,12 +1,94 @@
-use std::{alloc::Layout, any::TypeId, marker::PhantomData, mem::MaybeUninit, ptr::NonNull};
+use std::{
+    alloc::Layout,
+    fmt,
+    mem::MaybeUninit,
+    num::NonZeroU64,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+mod coroutine;
+mod local;
 mod sys;
 
+pub use coroutine::{Coroutine, Resumed, Suspender};
+pub use local::LocalKey;
+pub use sys::StackPolicy;
+
+/// A process-wide unique context identifier, never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContextId(NonZeroU64);
+
+impl ContextId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NonZeroU64::new(NEXT.fetch_add(1, Ordering::Relaxed)).expect("Context ids exhausted"))
+    }
+
+    #[inline(always)]
+    pub fn as_u64(self) -> u64 {
+        self.0.get()
+    }
+}
+
+impl fmt::Display for ContextId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The lifecycle state of a context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextState {
+    /// Not initialized yet
+    Created,
+    /// Initialized, it may be running or suspended
+    Started,
+    /// Its function returned
+    Done,
+}
+
+/// Why a swap between two contexts is invalid, see [`UContext::try_swap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    /// The context swapped from does not run on the current thread
+    Suspended(ContextId),
+    /// The context swapped to was not initialized
+    NotStarted(ContextId),
+    /// The function of the context swapped to returned
+    Finished(ContextId),
+    /// The context swapped to is already running on the current thread
+    Running(ContextId),
+    /// The context swapped to is running on another thread
+    RunningElsewhere(ContextId),
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Suspended(id) => write!(f, "Swapped from the suspended context {}", id),
+            Self::NotStarted(id) => write!(f, "Swapped to the uninitialized context {}", id),
+            Self::Finished(id) => write!(f, "Swapped to the finished context {}", id),
+            Self::Running(id) => write!(f, "Swapped to the running context {}", id),
+            Self::RunningElsewhere(id) => {
+                write!(f, "Swapped to the context {} running on another thread", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SwapError {}
+
 #[repr(transparent)]
 pub struct UContext(NonNull<InnerErazed>);
 impl Drop for UContext {
     fn drop(&mut self) {
         let ptr = self.0.as_ptr();
+        // Coroutine-local values must not be looked up in a released context
+        if std::ptr::eq(CURRENT_CTX.get(), ptr) {
+            CURRENT_CTX.set(std::ptr::null());
+        }
         unsafe {
             std::ptr::drop_in_place(ptr);
             std::alloc::dealloc(ptr as _, (&*ptr).vtable.layout);
@@ -39,7 +121,7 @@ impl UContext {
         if inner.is_null() {
             None
         } else {
-            unsafe { inner.write(InnerErazed::get()) };
+            unsafe { inner.write(InnerErazed::root()) };
             Some(Self(unsafe { NonNull::new_unchecked(inner) }))
         }
     }
@@ -54,16 +136,223 @@ impl UContext {
     pub fn init(&mut self) -> bool {
         unsafe { self.0.as_mut().init() }
     }
+    /// Suspends `self`, the context running on the current thread, and runs `other`
+    ///
+    /// # Panics
+    ///  - When the swap is invalid, see [`try_swap`](Self::try_swap), in debug builds or with
+    ///    the `checked` feature. Otherwise an invalid swap corrupts the contexts.
     #[inline(always)]
     pub fn swap(&mut self, other: &mut Self) {
         // println!("Swap: begin {:?}=>{:?}", self.0.as_ptr(), other.0.as_ptr());
         unsafe { self.0.as_mut().swap(other.0.as_mut()) };
         // println!("Swap: end");
     }
+
+    /// Suspends the context running on the current thread and runs `self` until it calls
+    /// [`suspend`](Self::suspend) or returns, then the current context continues
+    ///
+    /// The calling context is recorded: unlike with [`swap`](Self::swap), the context goes
+    /// back to whoever resumed it last instead of its exit context, so it may be driven from
+    /// different contexts over its lifetime. The root context of the thread is created when
+    /// the thread never swapped to a context.
+    ///
+    /// # Panics
+    ///  - When the swap is invalid, like [`swap`](Self::swap)
+    pub fn resume(&mut self) {
+        let mut caller = InnerErazed::current();
+        unsafe {
+            let ctx = self.0.as_mut();
+            // Validated first: a failed swap must not leave its caller behind
+            #[cfg(any(debug_assertions, feature = "checked"))]
+            if let Err(err) = caller.as_ref().check_swap(ctx) {
+                panic!("{}", err);
+            }
+            ctx.caller = Some(caller);
+            caller.as_mut().swap_unchecked(ctx);
+        }
+    }
+
+    /// Returns to the context which resumed the current context with [`resume`](Self::resume),
+    /// until it is resumed again
+    ///
+    /// # Panics
+    ///  - When the current context was not resumed, or already returned to its caller
+    pub fn suspend() {
+        let ctx = unsafe { (CURRENT_CTX.get() as *mut InnerErazed).as_mut() }
+            .expect("Suspended outside of a context");
+        let mut caller = ctx
+            .caller
+            .take()
+            .unwrap_or_else(|| panic!("Suspended the context {} which was not resumed", ctx.id));
+        ctx.swap(unsafe { caller.as_mut() });
+    }
+
+    /// Like [`swap`](Self::swap) but always validates the swap, fails without swapping when
+    /// `self` is not running on the current thread, or when `other` is not initialized,
+    /// finished or running. A context running on another thread is only detected with
+    /// `debug_assertions` or the `checked` feature.
+    pub fn try_swap(&mut self, other: &mut Self) -> Result<(), SwapError> {
+        unsafe {
+            self.0.as_ref().check_swap(other.0.as_ref())?;
+            self.0.as_mut().swap_unchecked(other.0.as_mut());
+        }
+        Ok(())
+    }
     #[inline(always)]
     pub fn is_movable(&self) -> bool {
         !unsafe { self.0.as_ref().is_local() }
     }
+
+    #[inline(always)]
+    pub fn id(&self) -> ContextId {
+        unsafe { self.0.as_ref().id }
+    }
+
+    pub fn state(&self) -> ContextState {
+        let flags = unsafe { self.0.as_ref().flags };
+        if (flags & FLAG_DONE) != 0 {
+            ContextState::Done
+        } else if (flags & FLAG_STARTED) != 0 {
+            ContextState::Started
+        } else {
+            ContextState::Created
+        }
+    }
+
+    /// Returns the usable size of the stack, 0 for a root context
+    pub fn stack_size(&self) -> usize {
+        let stack = unsafe { &self.0.as_ref().stack };
+        if stack.total_size() == 0 {
+            0
+        } else {
+            stack.size()
+        }
+    }
+
+    /// Returns the address range of the usable stack, empty for a root context and before
+    /// [`init`](Self::init)
+    pub fn stack_range(&self) -> Range<usize> {
+        let stack = unsafe { &self.0.as_ref().stack };
+        if stack.bottom().is_null() {
+            0..0
+        } else {
+            stack.bottom() as usize..stack.top() as usize
+        }
+    }
+
+    /// Returns `true` while a thread runs on the context
+    ///
+    /// Without `debug_assertions` or the `checked` feature, only the current thread is checked:
+    /// a context running on another thread is reported as not running.
+    #[inline(always)]
+    pub fn is_running(&self) -> bool {
+        unsafe { self.0.as_ref().is_running() }
+    }
+
+    /// Wraps a context created by [`movable`](Self::movable) so that it can be sent to another
+    /// thread, fails with the context back when it is pinned, a root context or running
+    ///
+    /// The exit context and the caller recorded by [`resume`](Self::resume) are cleared: they
+    /// belong to the current thread, the thread resuming the context must set its own.
+    ///
+    /// # Safety
+    ///  - No value which is not `Send` may be live on the stack of the context, e.g. an `Rc`,
+    ///    a `RefCell` borrow or a `MutexGuard` held across a suspension, as the context resumes
+    ///    on another thread. A context which never ran has nothing on its stack.
+    pub unsafe fn into_movable(mut self) -> Result<MovableUContext, Self> {
+        if !self.is_movable() || self.is_running() {
+            return Err(self);
+        }
+        self.set_exit_context(None);
+        unsafe { self.0.as_mut().caller = None };
+        Ok(MovableUContext(self))
+    }
+
+    /// Sets how the contexts initialized on the current thread allocate their stacks, returns
+    /// the previous policy
+    ///
+    /// The stacks released on the thread are kept in its pool, up to `policy.cache` stacks, and
+    /// reused by the next contexts of the same stack size. By default no stack is kept and the
+    /// stacks are not bound to a NUMA node.
+    pub fn set_stack_policy(policy: StackPolicy) -> StackPolicy {
+        sys::StackPool::set_policy(policy)
+    }
+
+    /// Returns the stack policy of the current thread
+    pub fn stack_policy() -> StackPolicy {
+        sys::StackPool::policy()
+    }
+
+    /// Returns the identifier of the context running on the current thread, `None` when the
+    /// thread never swapped to a context
+    pub fn current_id() -> Option<ContextId> {
+        let ctx = CURRENT_CTX.get();
+        if ctx.is_null() {
+            None
+        } else {
+            Some(unsafe { (*ctx).id })
+        }
+    }
+}
+
+impl fmt::Debug for UContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UContext")
+            .field("id", &self.id())
+            .field("state", &self.state())
+            .field("movable", &self.is_movable())
+            .finish()
+    }
+}
+
+/// A suspended context created by [`UContext::movable`], which may be sent to another thread
+///
+/// See [`UContext::into_movable`] and [`migrate`](Self::migrate).
+#[repr(transparent)]
+pub struct MovableUContext(UContext);
+
+// Safety: the closure, output and coroutine-local values of a movable context are `Send`, the
+// context is suspended, and the caller of `into_movable` guarantees that the values live on its
+// stack are `Send`
+unsafe impl Send for MovableUContext {}
+
+impl MovableUContext {
+    /// Returns a movable context, see [`UContext::movable`]
+    pub fn new<F: FnOnce() + Send + 'static>(f: F, stack_size_hint: usize) -> Option<Self> {
+        UContext::movable(f, stack_size_hint).map(Self)
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> ContextId {
+        self.0.id()
+    }
+
+    #[inline(always)]
+    pub fn state(&self) -> ContextState {
+        self.0.state()
+    }
+
+    /// Returns the context to resume it on the current thread
+    ///
+    /// # Panics
+    ///  - When the context is running, e.g. resumed by a context holding it as exit context
+    pub fn migrate(self) -> UContext {
+        assert!(
+            !self.0.is_running(),
+            "Context {} migrated while running",
+            self.0.id()
+        );
+        self.0
+    }
+}
+
+impl fmt::Debug for MovableUContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MovableUContext")
+            .field("id", &self.id())
+            .field("state", &self.state())
+            .finish()
+    }
 }
 
 type StartCb = unsafe extern "C" fn(thiz: *mut InnerErazed);
@@ -73,7 +362,11 @@ const FLAG_LOCAL: usize = 1usize << 0;
 const FLAG_STARTED: usize = 1usize << 1;
 const FLAG_DONE: usize = 1usize << 2;
 const FLAG_HAS_OUTPUT: usize = 1usize << 3;
+const FLAG_ENTERED: usize = 1usize << 4;
+const FLAG_ROOT: usize = 1usize << 5;
 thread_local! {
+    /// The root context of the thread, created on its first resume when it never swapped before
+    static THREAD_ROOT: std::cell::RefCell<Option<UContext>> = const { std::cell::RefCell::new(None) };
     static CURRENT_CTX: std::cell::Cell<*const InnerErazed>  = const { std::cell::Cell::new(std::ptr::null_mut()) };
 }
 
@@ -84,13 +377,22 @@ struct VTable {
 }
 struct InnerErazed {
     vtable: &'static VTable,
+    id: ContextId,
     flags: usize,
     stack_pointer: *mut (),
     exit_context: Option<NonNull<InnerErazed>>,
+    /// The context which resumed it, until it suspends or returns
+    caller: Option<NonNull<InnerErazed>>,
     stack: sys::Stack,
+    locals: local::Locals,
+    /// Set while a thread runs on the context, read by other threads. Only maintained by the
+    /// checked builds, it costs two stores per swap.
+    #[cfg(any(debug_assertions, feature = "checked"))]
+    running: std::sync::atomic::AtomicBool,
 }
 impl Drop for InnerErazed {
     fn drop(&mut self) {
+        log::trace!("Context {} released", self.id);
         unsafe { (self.vtable.drop_erased)(self as _) };
     }
 }
@@ -108,29 +410,52 @@ impl InnerErazed {
     fn make(vtable: &'static VTable, flags: usize, size_hint: usize) -> Self {
         Self {
             vtable,
+            id: ContextId::next(),
             flags,
             stack_pointer: std::ptr::null_mut(),
             exit_context: None,
+            caller: None,
             stack: sys::Stack::with_size(size_hint),
+            locals: local::Locals::new(),
+            #[cfg(any(debug_assertions, feature = "checked"))]
+            running: std::sync::atomic::AtomicBool::new(false),
         }
     }
 
-    const fn get() -> Self {
+    fn root() -> Self {
         Self {
             vtable: &Self::ROOT_VTABLE,
-            flags: FLAG_LOCAL | FLAG_STARTED,
-            stack_pointer: 0xDEADBEEF as usize as _,
+            id: ContextId::next(),
+            flags: FLAG_LOCAL | FLAG_STARTED | FLAG_ROOT,
+            stack_pointer: 0xDEADBEEF_usize as _,
             exit_context: None,
+            caller: None,
             stack: sys::Stack::root_stack(),
+            locals: local::Locals::new(),
+            // The calling thread runs on it
+            #[cfg(any(debug_assertions, feature = "checked"))]
+            running: std::sync::atomic::AtomicBool::new(true),
+        }
+    }
+
+    /// Returns the context running on the current thread
+    fn current() -> NonNull<Self> {
+        match NonNull::new(CURRENT_CTX.get() as *mut Self) {
+            Some(ctx) => ctx,
+            None => THREAD_ROOT.with(|root| {
+                let mut root = root.borrow_mut();
+                let root = root.get_or_insert_with(|| {
+                    UContext::get().expect("Failed to allocate the root context")
+                });
+                CURRENT_CTX.set(root.0.as_ptr());
+                root.0
+            }),
         }
     }
 
     fn init(&mut self) -> bool {
         assert!((self.flags & FLAG_STARTED) == 0);
-        if !self
-            .stack
-            .allocate(&mut sys::StackPool::new(sys::Stack::DEFAULT_TOTAL_SIZE))
-        {
+        if !self.stack.allocate() {
             return false;
         }
         self.stack.register();
@@ -143,26 +468,90 @@ impl InnerErazed {
             start_arg,
         );
         self.flags |= FLAG_STARTED;
+        log::trace!(
+            "Context {} initialized with a {} bytes stack",
+            self.id,
+            self.stack.size()
+        );
         true
     }
 
+    /// Returns `true` when the current thread runs on `self`
+    ///
+    /// The roots all stand for the native stack of their thread: any of them runs while the
+    /// thread is not on a coroutine stack.
+    fn is_current(&self) -> bool {
+        match unsafe { CURRENT_CTX.get().as_ref() } {
+            Some(current) if !current.is_root() => std::ptr::eq(current, self),
+            _ => self.is_root(),
+        }
+    }
+
+    #[inline(always)]
+    fn is_root(&self) -> bool {
+        (self.flags & FLAG_ROOT) != 0
+    }
+
+    /// Returns `true` while a thread runs on the context, only the current thread is checked by
+    /// the unchecked builds
+    fn is_running(&self) -> bool {
+        #[cfg(any(debug_assertions, feature = "checked"))]
+        return self.running.load(Ordering::Acquire);
+        #[cfg(not(any(debug_assertions, feature = "checked")))]
+        return self.is_current();
+    }
+
+    /// Validates a swap from `self` to `other`
+    fn check_swap(&self, other: &Self) -> Result<(), SwapError> {
+        if !self.is_current() {
+            Err(SwapError::Suspended(self.id))
+        } else if (other.flags & FLAG_DONE) != 0 {
+            Err(SwapError::Finished(other.id))
+        } else if (other.flags & FLAG_STARTED) == 0 {
+            Err(SwapError::NotStarted(other.id))
+        } else if std::ptr::eq(self, other) || other.is_current() {
+            Err(SwapError::Running(other.id))
+        } else if other.is_running() {
+            Err(SwapError::RunningElsewhere(other.id))
+        } else {
+            Ok(())
+        }
+    }
+
     #[inline(always)]
     fn swap(&mut self, other: &mut Self) {
-        debug_assert!((self.flags & FLAG_STARTED) & (other.flags & FLAG_STARTED) != 0);
+        #[cfg(any(debug_assertions, feature = "checked"))]
+        if let Err(err) = self.check_swap(other) {
+            panic!("{}", err);
+        }
+        self.swap_unchecked(other);
+    }
 
+    #[inline(always)]
+    fn swap_unchecked(&mut self, other: &mut Self) {
         // println!(
         //     "-Swap: begin {:?}=>{:?}",
         //     self.stack_pointer, other.stack_pointer
         // );
-        // unsafe { other.caller = Some(NonNull::new_unchecked(self as _)) };
         CURRENT_CTX.set(other as _);
+        #[cfg(any(debug_assertions, feature = "checked"))]
+        {
+            self.running.store(false, Ordering::Release);
+            other.running.store(true, Ordering::Release);
+        }
         unsafe { __xaio_uctx_asm_swap(&mut self.stack_pointer, other.stack_pointer) };
     }
+    fn start_prolog(&mut self) {
+        // The closure is moved out of the context: it must not be dropped with it anymore
+        self.flags |= FLAG_ENTERED;
+        log::trace!("Context {} started", self.id);
+    }
+
     fn start_epilog(&mut self) {
         self.flags |= FLAG_DONE;
-        if let Some(exit_context) = self.exit_context.as_mut() {
-            let caller = unsafe { exit_context.as_mut() };
-            self.swap(caller);
+        log::trace!("Context {} done", self.id);
+        if let Some(mut caller) = self.caller.take().or(self.exit_context) {
+            self.swap(unsafe { caller.as_mut() });
         } else {
             die("Coroutine exited without a defined exit-context");
         }
@@ -218,23 +607,18 @@ impl<F: FnOnce() -> O + 'static, O> InnerLocal<F, O> {
             })
         }
     }
-    fn make(f: F) -> Option<NonNull<InnerErazed>> {
-        Self::make_with_size(f, sys::Stack::DEFAULT_TOTAL_SIZE - sys::Stack::guard_size())
-    }
     unsafe extern "C" fn drop_erased(thiz: *mut InnerErazed) {
         let thiz = unsafe { &mut *std::mem::transmute::<*mut InnerErazed, *mut Self>(thiz) };
-        println!("drop_erased1");
-        if (thiz.as_inner.flags & FLAG_DONE) == 0 {
-            println!(" - drop_erased: cb");
+        if (thiz.as_inner.flags & FLAG_ENTERED) == 0 {
             thiz.f.assume_init_drop();
         }
-        if (thiz.as_inner.flags & FLAG_HAS_OUTPUT) == 0 {
-            println!(" - drop_erased: cb");
+        if (thiz.as_inner.flags & FLAG_HAS_OUTPUT) != 0 {
             thiz.o.assume_init_drop();
         }
     }
     unsafe extern "C" fn start(thiz: *mut InnerErazed) {
         let thiz = unsafe { &mut *std::mem::transmute::<*mut InnerErazed, *mut Self>(thiz) };
+        thiz.as_inner.start_prolog();
         thiz.o.write((unsafe { thiz.f.assume_init_read() })());
         thiz.as_inner.flags |= FLAG_HAS_OUTPUT;
         thiz.as_inner.start_epilog();
@@ -274,24 +658,20 @@ impl<F: FnOnce() -> O + Send + 'static, O: Send> InnerShared<F, O> {
             })
         }
     }
-    fn make(f: F) -> Option<NonNull<InnerErazed>> {
-        Self::make_with_size(f, sys::Stack::DEFAULT_TOTAL_SIZE - sys::Stack::guard_size())
-    }
     unsafe extern "C" fn drop_erased(thiz: *mut InnerErazed) {
         let thiz = unsafe { &mut *std::mem::transmute::<*mut InnerErazed, *mut Self>(thiz) };
-        println!("drop_erased1");
-        if (thiz.as_inner.flags & FLAG_DONE) == 0 {
-            println!(" - drop_erased: cb");
+        if (thiz.as_inner.flags & FLAG_ENTERED) == 0 {
             thiz.f.assume_init_drop();
         }
-        if (thiz.as_inner.flags & FLAG_HAS_OUTPUT) == 0 {
-            println!(" - drop_erased: cb");
+        if (thiz.as_inner.flags & FLAG_HAS_OUTPUT) != 0 {
             thiz.o.assume_init_drop();
         }
     }
     unsafe extern "C" fn start(thiz: *mut InnerErazed) {
         let thiz = unsafe { &mut *std::mem::transmute::<*mut InnerErazed, *mut Self>(thiz) };
+        thiz.as_inner.start_prolog();
         thiz.o.write((unsafe { thiz.f.assume_init_read() })());
+        thiz.as_inner.flags |= FLAG_HAS_OUTPUT;
         thiz.as_inner.start_epilog();
     }
 }
@@ -308,26 +688,6 @@ unsafe extern "C" {
     unsafe fn __xaio_uctx_asm_prefetch(sp: *const ());
 }
 
-#[inline]
-#[cold]
-fn cold() {}
-
-#[inline]
-fn likely(b: bool) -> bool {
-    if !b {
-        cold()
-    }
-    b
-}
-
-#[inline]
-fn unlikely(b: bool) -> bool {
-    if b {
-        cold()
-    }
-    b
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -340,9 +700,139 @@ mod tests {
         uctx.set_exit_context(Some(&root));
         root.swap(&mut uctx);
 
+        assert_eq!(uctx.state(), ContextState::Done);
+        assert_eq!(UContext::current_id(), Some(root.id()));
+
         let mut uctx = UContext::movable(|| println!("Run"), UContext::default_size()).unwrap();
+        assert_eq!(uctx.state(), ContextState::Created);
+        assert_ne!(uctx.id(), root.id());
         assert!(uctx.init());
         uctx.set_exit_context(Some(&root));
         root.swap(&mut uctx);
+        assert_eq!(
+            root.try_swap(&mut uctx),
+            Err(SwapError::Finished(uctx.id()))
+        );
+        let mut uctx = UContext::pinned(|| (), UContext::default_size()).unwrap();
+        assert_eq!(
+            root.try_swap(&mut uctx),
+            Err(SwapError::NotStarted(uctx.id()))
+        );
+        assert!(uctx.init());
+        assert_eq!(
+            uctx.try_swap(&mut root),
+            Err(SwapError::Suspended(uctx.id()))
+        );
+        uctx.set_exit_context(Some(&root));
+        assert_eq!(root.try_swap(&mut uctx), Ok(()));
+    }
+
+    #[test]
+    fn test_swap_from_other_root() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let result = Rc::new(RefCell::new(None));
+        let their_result = result.clone();
+        let mut root = UContext::get().unwrap();
+        let mut uctx = UContext::pinned(
+            move || {
+                // A root created while a coroutine runs does not run
+                let mut other = UContext::get().unwrap();
+                let mut target = UContext::pinned(|| (), UContext::default_size()).unwrap();
+                assert!(target.init());
+                *their_result.borrow_mut() = Some(other.try_swap(&mut target).map_err(|err| {
+                    assert_eq!(err, SwapError::Suspended(other.id()));
+                }));
+            },
+            UContext::default_size(),
+        )
+        .unwrap();
+        assert!(uctx.init());
+        uctx.set_exit_context(Some(&root));
+        root.swap(&mut uctx);
+        assert_eq!(*result.borrow(), Some(Err(())));
+    }
+
+    #[test]
+    fn test_resume() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let their_log = log.clone();
+        let shared = Rc::new(RefCell::new(
+            UContext::pinned(
+                move || {
+                    their_log.borrow_mut().push(UContext::current_id());
+                    UContext::suspend();
+                    their_log.borrow_mut().push(UContext::current_id());
+                },
+                UContext::default_size(),
+            )
+            .unwrap(),
+        ));
+        assert!(shared.borrow_mut().init());
+        shared.borrow_mut().resume();
+        let root = UContext::current_id();
+
+        // Completed from another context, which gets the control back
+        let their_shared = shared.clone();
+        let mut driver = UContext::pinned(
+            move || {
+                their_shared.borrow_mut().resume();
+                assert_eq!(their_shared.borrow().state(), ContextState::Done);
+            },
+            UContext::default_size(),
+        )
+        .unwrap();
+        assert!(driver.init());
+        driver.resume();
+        assert_eq!(driver.state(), ContextState::Done);
+        assert_eq!(UContext::current_id(), root);
+        let id = shared.borrow().id();
+        assert_eq!(*log.borrow(), [Some(id), Some(id)]);
+    }
+
+    #[cfg(any(debug_assertions, feature = "checked"))]
+    #[test]
+    fn test_resume_invalid() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut uctx = UContext::pinned(|| (), UContext::default_size()).unwrap();
+        assert!(catch_unwind(AssertUnwindSafe(|| uctx.resume())).is_err());
+        assert!(unsafe { uctx.0.as_ref().caller }.is_none());
+        assert!(uctx.init());
+        uctx.resume();
+        assert_eq!(uctx.state(), ContextState::Done);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let pinned = UContext::pinned(|| (), UContext::default_size()).unwrap();
+        assert!(unsafe { pinned.into_movable() }.is_err());
+        let root = UContext::get().unwrap();
+        assert!(root.is_running());
+        assert!(unsafe { root.into_movable() }.is_err());
+
+        let mut uctx = UContext::movable(
+            || assert!(UContext::current_id().is_some()),
+            UContext::default_size(),
+        )
+        .unwrap();
+        assert!(uctx.init());
+        // Nothing ran on its stack yet
+        let movable = unsafe { uctx.into_movable() }.unwrap();
+        let id = movable.id();
+        std::thread::spawn(move || {
+            let mut root = UContext::get().unwrap();
+            let mut uctx = movable.migrate();
+            uctx.set_exit_context(Some(&root));
+            root.swap(&mut uctx);
+            assert_eq!(uctx.id(), id);
+            assert_eq!(uctx.state(), ContextState::Done);
+            assert!(!uctx.is_running());
+            assert!(root.is_running());
+        })
+        .join()
+        .unwrap();
     }
 }
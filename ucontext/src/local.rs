@@ -0,0 +1,154 @@
+use std::{any::Any, cell::RefCell, fmt};
+
+use crate::CURRENT_CTX;
+
+/// The coroutine-local values of a context, dropped with it
+pub(crate) struct Locals {
+    /// Values by key address, there are only a few keys
+    values: RefCell<Vec<(usize, Box<dyn Any + Send>)>>,
+}
+
+impl Locals {
+    pub(crate) const fn new() -> Self {
+        Self {
+            values: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn get(&self, key: usize) -> Option<*const (dyn Any + Send)> {
+        self.values
+            .borrow()
+            .iter()
+            .find(|(other, _)| *other == key)
+            .map(|(_, value)| &**value as *const _)
+    }
+}
+
+thread_local! {
+    /// The values of the thread, shared by its root contexts
+    static THREAD_LOCALS: Locals = const { Locals::new() };
+}
+
+/// Returns the values of the running context, or of the thread outside of coroutines
+fn current_locals() -> *const Locals {
+    let ctx = CURRENT_CTX.get();
+    if ctx.is_null() || unsafe { (*ctx).is_root_ctx() } {
+        THREAD_LOCALS.with(|locals| locals as *const _)
+    } else {
+        unsafe { &(*ctx).locals as *const _ }
+    }
+}
+
+/// A key to a coroutine-local value, declared by [`coroutine_local!`](crate::coroutine_local)
+///
+/// Each context lazily gets its own value, dropped with the context. Outside of coroutines, the
+/// thread has its own value, dropped with the thread and shared by its root contexts (see
+/// [`UContext::get`](crate::UContext::get)). Values must be `Send` as movable contexts take them
+/// along when they migrate.
+pub struct LocalKey<T: Send + 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Calls `f` with the value of the running context, initializing it on first access
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let key = self as *const Self as usize;
+        // Safety: the values of the running context outlive `f` and are never removed before the
+        // context is dropped, their boxes do not move when the list grows
+        let locals = unsafe { &*current_locals() };
+        let value = match locals.get(key) {
+            Some(value) => value,
+            None => {
+                // `init` may access other keys
+                let value: Box<dyn Any + Send> = Box::new((self.init)());
+                let mut values = locals.values.borrow_mut();
+                values.push((key, value));
+                &*values.last().unwrap().1 as *const _
+            }
+        };
+        f(unsafe { &*value }.downcast_ref::<T>().unwrap())
+    }
+}
+
+impl<T: Send + 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey").finish_non_exhaustive()
+    }
+}
+
+/// Declares coroutine-local keys, like [`thread_local!`] but with one value per context
+///
+/// ```
+/// use std::cell::Cell;
+///
+/// ucontext::coroutine_local! {
+///     static DEPTH: Cell<u32> = Cell::new(0);
+/// }
+///
+/// DEPTH.with(|depth| depth.set(depth.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! coroutine_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])* $vis static $name: $crate::LocalKey<$t> = $crate::LocalKey::new(|| $init);
+        $crate::coroutine_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])* $vis static $name: $crate::LocalKey<$t> = $crate::LocalKey::new(|| $init);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::UContext;
+    use std::{cell::Cell, sync::atomic::AtomicUsize, sync::atomic::Ordering};
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counter(Cell<u32>);
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    coroutine_local! {
+        static COUNTER: Counter = Counter(Cell::new(0));
+    }
+
+    fn bump() -> u32 {
+        COUNTER.with(|counter| {
+            counter.0.set(counter.0.get() + 1);
+            counter.0.get()
+        })
+    }
+
+    #[test]
+    fn test_coroutine_local() {
+        assert_eq!(bump(), 1);
+        let mut root = UContext::get().unwrap();
+        let mut uctx =
+            UContext::pinned(|| assert_eq!(bump(), 1), UContext::default_size()).unwrap();
+        assert!(uctx.init());
+        uctx.set_exit_context(Some(&root));
+        root.swap(&mut uctx);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+        drop(uctx);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        // Back on the root context, which shares the value of the thread
+        assert_eq!(bump(), 2);
+        drop(root);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        assert_eq!(bump(), 3);
+    }
+}
@@ -8,20 +8,21 @@ cfg_if::cfg_if! {
 }
 
 struct ValgrindStackId {
-    #[cfg(test)]
+    #[cfg(feature = "valgrind")]
     id: usize,
 }
 
-use std::{ops::Range, ptr::NonNull};
+use std::{cell::RefCell, ptr::NonNull};
 
-#[cfg(test)]
+#[cfg(feature = "valgrind")]
 use crabgrind as cg;
 
 impl ValgrindStackId {
+    #[cfg(feature = "valgrind")]
     const INVALID: usize = usize::MAX;
     const fn default() -> Self {
         cfg_if::cfg_if! {
-            if #[cfg(test)] {
+            if #[cfg(feature = "valgrind")] {
                 Self { id: Self::INVALID }
             } else {
                 Self {}
@@ -31,7 +32,7 @@ impl ValgrindStackId {
 
     fn register(&mut self, _bottom: *mut libc::c_void, _top: *mut libc::c_void) {
         cfg_if::cfg_if! {
-            if #[cfg(test)] {
+            if #[cfg(feature = "valgrind")] {
                 debug_assert!(self.id == Self::INVALID);
                 self.id = if cg::run_mode() != cg::RunMode::Native {
                     cg::memcheck::stack::register(_bottom, _top)
@@ -43,7 +44,7 @@ impl ValgrindStackId {
     }
     fn deregister(&mut self) {
         cfg_if::cfg_if! {
-            if #[cfg(test)] {
+            if #[cfg(feature = "valgrind")] {
                 if self.id != Self::INVALID {
                     cg::memcheck::stack::deregister(self.id);
                     self.id = Self::INVALID;
@@ -51,16 +52,6 @@ impl ValgrindStackId {
             }
         }
     }
-    #[inline]
-    fn is_registered(&self) -> bool {
-        cfg_if::cfg_if! {
-            if #[cfg(test)] {
-                self.id != Self::INVALID
-            } else {
-                false
-            }
-        }
-    }
 }
 
 /// A coroutine stack
@@ -69,38 +60,92 @@ pub struct Stack {
     total_size: usize,
     /// The start end of the stack to use as a coroutine stack
     bottom: *mut u8,
-    /// The valdring stack identifier (When running tests under valgrind)
+    /// The NUMA node the stack memory is bound to
+    node: Option<u32>,
+    /// The valgrind stack identifier (With the `valgrind` feature)
     valgrind_stack_id: ValgrindStackId,
 }
 
+/// How the contexts initialized on a thread allocate their stacks, see
+/// [`UContext::set_stack_policy`](crate::UContext::set_stack_policy)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StackPolicy {
+    /// Number of released stacks the thread keeps for reuse instead of unmapping them
+    pub cache: usize,
+    /// The NUMA node the memory of the new stacks is bound to (Linux only), the kernel falls
+    /// back to the other nodes when it is exhausted
+    pub node: Option<u32>,
+}
+
+/// The released stacks of a thread, all bound to the node of its policy
 pub struct StackPool {
-    total_size: usize,
+    policy: StackPolicy,
+    /// Total size and bottom of each free stack
+    free: Vec<(usize, NonNull<u8>)>,
+}
+
+thread_local! {
+    static POOL: RefCell<StackPool> = const {
+        RefCell::new(StackPool {
+            policy: StackPolicy {
+                cache: 0,
+                node: None,
+            },
+            free: Vec::new(),
+        })
+    };
 }
+
 impl StackPool {
-    pub fn new(total_size: usize) -> Self {
-        Self {
-            total_size: total_size,
-        }
+    /// Sets the policy of the current thread, returns the previous one
+    pub fn set_policy(policy: StackPolicy) -> StackPolicy {
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            let previous = std::mem::replace(&mut pool.policy, policy);
+            if previous.node != policy.node {
+                pool.trim(0);
+            } else {
+                pool.trim(policy.cache);
+            }
+            previous
+        })
     }
-    pub fn total_size(&self) -> usize {
-        self.total_size
+
+    /// Returns the policy of the current thread
+    pub fn policy() -> StackPolicy {
+        POOL.with(|pool| pool.borrow().policy)
     }
+
     fn get(&mut self, total_size: usize) -> Option<NonNull<u8>> {
-        None // TODO:
+        let i = self.free.iter().position(|(size, _)| *size == total_size)?;
+        Some(self.free.swap_remove(i).1)
+    }
+
+    fn put(&mut self, total_size: usize, node: Option<u32>, bottom: NonNull<u8>) -> bool {
+        if self.free.len() >= self.policy.cache || node != self.policy.node {
+            return false;
+        }
+        self.free.push((total_size, bottom));
+        true
     }
-    fn put(&mut self, total_size: usize, base: NonNull<u8>) -> bool {
-        false
+
+    fn trim(&mut self, len: usize) {
+        while self.free.len() > len {
+            let (total_size, bottom) = self.free.pop().unwrap();
+            stack_dealloc(total_size, Stack::guard_size(), bottom);
+        }
+    }
+}
+
+impl Drop for StackPool {
+    fn drop(&mut self) {
+        self.trim(0);
     }
 }
 
 impl Drop for Stack {
     fn drop(&mut self) {
-        if !self.bottom.is_null() {
-            self.valgrind_stack_id.deregister();
-            stack_dealloc(self.total_size, Self::guard_size(), unsafe {
-                NonNull::new_unchecked(self.bottom)
-            });
-        }
+        unsafe { self.deallocate() };
     }
 }
 
@@ -135,7 +180,7 @@ impl Stack {
     }
     #[inline(always)]
     pub(crate) fn top(&self) -> *mut u8 {
-        unsafe { self.bottom.offset(self.size() as isize) }
+        unsafe { self.bottom.add(self.size()) }
     }
 
     #[inline(always)]
@@ -143,68 +188,53 @@ impl Stack {
         self.valgrind_stack_id
             .register(self.bottom() as _, self.top() as _);
     }
-    #[inline(always)]
-    pub fn deregister(&mut self) {
-        self.valgrind_stack_id.deregister();
-    }
-    #[inline(always)]
-    pub fn is_registered(&self) -> bool {
-        self.valgrind_stack_id.is_registered()
-    }
 
-    pub fn allocate(&mut self, pool: &mut StackPool) -> bool {
+    /// Allocates the stack from the pool of the current thread, or maps a new one
+    pub fn allocate(&mut self) -> bool {
         assert!(self.bottom.is_null());
-        if let Some(base) = pool.get(self.total_size) {
+        let (cached, node) = POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            (pool.get(self.total_size), pool.policy.node)
+        });
+        self.node = node;
+        if let Some(base) = cached {
             self.bottom = base.as_ptr();
             true
         } else if let Some(base) = stack_alloc(self.total_size, Self::guard_size()) {
             self.bottom = base.as_ptr();
-            println!(
-                "::allocate(), bottom:{:?}, top:{:?}",
-                self.bottom(),
-                self.top()
-            );
+            if let Some(node) = node {
+                if !stack_bind(self.total_size, Self::guard_size(), base, node) {
+                    log::debug!("Failed to bind a stack to NUMA node {}", node);
+                }
+            }
             true
         } else {
             false
         }
     }
+
+    /// Releases the stack to the pool of the current thread, or unmaps it
+    ///
     /// # Safety
     ///  - Stack **MUST NOT** be used
-    pub unsafe fn deallocate(&mut self, pool: &mut StackPool) {
-        if !self.bottom.is_null() {
+    pub unsafe fn deallocate(&mut self) {
+        if let Some(bottom) = NonNull::new(self.bottom) {
             self.valgrind_stack_id.deregister();
-            if !pool.put(self.total_size, unsafe {
-                NonNull::new_unchecked(self.bottom as _)
-            }) {
-                stack_dealloc(self.total_size, Self::guard_size(), unsafe {
-                    NonNull::new_unchecked(self.bottom)
-                });
+            let cached = POOL
+                .try_with(|pool| pool.borrow_mut().put(self.total_size, self.node, bottom))
+                .unwrap_or(false);
+            if !cached {
+                stack_dealloc(self.total_size, Self::guard_size(), bottom);
             }
             self.bottom = std::ptr::null_mut();
         }
     }
 
-    pub fn guard_range(&self) -> Range<usize> {
-        if self.bottom.is_null() {
-            Range { start: 0, end: 0 }
-        } else {
-            let guard = if crate::sys::stack_growth_downward() {
-                unsafe { self.bottom.offset(-(Self::guard_size() as isize)) }
-            } else {
-                unsafe { self.bottom.offset(self.size() as isize) }
-            } as usize;
-            Range {
-                start: guard,
-                end: guard + Self::guard_size(),
-            }
-        }
-    }
-
     pub const fn root_stack() -> Self {
         Self {
             total_size: 0,
             bottom: std::ptr::null_mut(),
+            node: None,
             valgrind_stack_id: ValgrindStackId::default(),
         }
     }
@@ -218,14 +248,10 @@ impl Stack {
         Self {
             total_size: size_hint,
             bottom: std::ptr::null_mut(),
+            node: None,
             valgrind_stack_id: ValgrindStackId::default(),
         }
     }
-
-    /// Returns a new stack with the default size or `None` when the system is out of memory
-    pub fn new() -> Stack {
-        Self::with_size(Self::DEFAULT_TOTAL_SIZE - Self::guard_size())
-    }
 }
 
 cfg_if::cfg_if! {
@@ -271,7 +297,28 @@ mod tests {
             Stack::page_size() + Stack::guard_size() * 4
         );
 
-        let stack = Stack::new();
+        let stack = Stack::with_size(Stack::DEFAULT_TOTAL_SIZE - Stack::guard_size());
         assert_eq!(stack.total_size, Stack::DEFAULT_TOTAL_SIZE);
     }
+
+    #[test]
+    fn test_pool() {
+        let previous = StackPool::set_policy(StackPolicy {
+            cache: 1,
+            node: Some(0),
+        });
+        let mut stack = Stack::with_size(Stack::DEFAULT_TOTAL_SIZE - Stack::guard_size());
+        assert!(stack.allocate());
+        let bottom = stack.bottom();
+        drop(stack);
+        // Reused by a stack of the same size only
+        let mut other = Stack::with_size(Stack::page_size());
+        assert!(other.allocate());
+        assert_ne!(other.bottom(), bottom);
+        let mut stack = Stack::with_size(Stack::DEFAULT_TOTAL_SIZE - Stack::guard_size());
+        assert!(stack.allocate());
+        assert_eq!(stack.bottom(), bottom);
+        unsafe { stack.bottom().write(1) };
+        StackPool::set_policy(previous);
+    }
 }
@@ -37,29 +37,68 @@ pub(crate) fn stack_alloc(total_size: usize, guard_size: usize) -> Option<NonNul
         let mut base = base as *mut u8;
         let mut guard = base;
         let top = base;
-        let top = unsafe { top.offset(total_size as isize) };
+        let top = unsafe { top.add(total_size) };
         if crate::sys::stack_growth_downward() {
-            base = unsafe { base.offset(guard_size as isize) };
+            base = unsafe { base.add(guard_size) };
         } else {
-            guard = unsafe { guard.offset((total_size - guard_size) as isize) }
+            guard = unsafe { guard.add(total_size - guard_size) }
         }
-        println!(
-            "stack_alloc() guarq:{:?}, bottom:{:?}, bottom-guard:{:?}, top:{:?}, top-bottom:{:?}",
+        log::trace!(
+            "Stack allocated: guard {:?}, bottom {:?}, top {:?}, {} bytes",
             guard,
             base,
-            (base as isize) - (guard as isize),
             top,
-            (top as isize) - (base as isize),
+            total_size
         );
         assert!(unsafe { libc::mprotect(guard as _, guard_size, libc::PROT_NONE) } >= 0);
         Some(unsafe { NonNull::new_unchecked(base) })
     }
 }
 
+/// Binds the memory of a stack allocated by [`stack_alloc`] to NUMA `node`, the kernel prefers
+/// it for the pages faulted in later
+pub(crate) fn stack_bind(
+    total_size: usize,
+    guard_size: usize,
+    base: NonNull<u8>,
+    node: u32,
+) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            const MPOL_PREFERRED: libc::c_long = 1;
+            const BITS: usize = libc::c_ulong::BITS as usize;
+            let mut base = base.as_ptr();
+            if crate::sys::stack_growth_downward() {
+                base = unsafe { base.offset(-(guard_size as isize)) };
+            }
+            let node = node as usize;
+            let mut mask = vec![0 as libc::c_ulong; node / BITS + 1];
+            mask[node / BITS] |= 1 << (node % BITS);
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_mbind,
+                    base,
+                    total_size,
+                    MPOL_PREFERRED,
+                    mask.as_ptr(),
+                    // The kernel only reads `maxnode - 1` bits
+                    mask.len() * BITS + 1,
+                    0,
+                )
+            };
+            res == 0
+        } else {
+            let _ = (total_size, guard_size, base, node);
+            false
+        }
+    }
+}
+
 pub(crate) fn stack_dealloc(total_size: usize, guard_size: usize, base: NonNull<u8>) {
     let mut base = base.as_ptr();
     if crate::sys::stack_growth_downward() {
         base = unsafe { base.offset(-(guard_size as isize)) };
     }
     assert!(unsafe { libc::munmap(base as _, total_size) } >= 0);
+    log::trace!("Stack freed: {:?}, {} bytes", base, total_size);
 }
@@ -17,12 +17,11 @@ cfg_if::cfg_if! {
         pub(crate) fn setup_coroutine_on_stack(stack: &mut crate::sys::Stack, start_cb: StartCb, start_arg: *mut ()) -> *mut () {
             unsafe {
                 let mut sp: *mut usize = stack.top() as _;
-                println!("ICI: sp:{:?}", sp);
                 // Leave 128 bytes at the top of the stack
                 sp = sp.offset(-16);
                 // Unreachable return address
                 sp = sp.offset(-1);
-                sp.write(__unreachable as usize);
+                sp.write(__unreachable as *const () as usize);
                 // Start argument
                 sp = sp.offset(-1);
                 sp.write(start_arg as usize);
@@ -32,7 +31,7 @@ cfg_if::cfg_if! {
                 // The trampoline is necessary because we can't set rdi and rsi using just
                 // a stack ; we use ASM to pop task_start_arg to rdi and call task_start_cb
                 sp = sp.offset(-1);
-                sp.write(__xaio_uctx_asm_boot as usize);
+                sp.write(__xaio_uctx_asm_boot as *const () as usize);
                 // rbp, rbx, r12, r13, r14 and r15 in mbrt_uctx_asm_sysv_x86_64.S
                 sp = sp.offset(-6);
                 // WARNING: stack MUST be aligned on 16 bytes